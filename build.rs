@@ -0,0 +1,92 @@
+// Compiles `instructions.in`'s declarative cycle-timing rules into the
+// generated `CYCLE_TABLE`/`CB_CYCLE_TABLE` arrays that `src/emu/opcodes.rs`
+// includes. See `instructions.in` for the rule grammar. Keeping this in one
+// text file instead of scattered `match`/array-literal logic is what let
+// chunk0-4's hand-rolled tables turn into a single generated source of
+// truth instead of numbers that could quietly drift from each other.
+use std::env;
+use std::fs;
+use std::path::Path;
+
+fn main() {
+    let manifest_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+    let src_path = Path::new(&manifest_dir).join("instructions.in");
+    println!("cargo:rerun-if-changed={}", src_path.display());
+
+    let text = fs::read_to_string(&src_path)
+        .unwrap_or_else(|e| panic!("failed to read {}: {e}", src_path.display()));
+
+    let mut main_table = [4u8; 256];
+    let mut cb_table = [8u8; 256];
+
+    for (lineno, raw_line) in text.lines().enumerate() {
+        let line = match raw_line.find('#') {
+            Some(i) => &raw_line[..i],
+            None => raw_line,
+        }
+        .trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        // A trailing `; note` is documentation only.
+        let line = match line.find(';') {
+            Some(i) => &line[..i],
+            None => line,
+        }
+        .trim();
+
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields.as_slice() {
+            ["default", table, cycles] => {
+                let cycles = parse_u8(cycles, lineno);
+                let arr = select_table(table, &mut main_table, &mut cb_table, lineno);
+                arr.fill(cycles);
+            }
+            ["set", table, opcode, cycles] => {
+                let opcode = parse_opcode(opcode, lineno);
+                let cycles = parse_u8(cycles, lineno);
+                let arr = select_table(table, &mut main_table, &mut cb_table, lineno);
+                arr[opcode as usize] = cycles;
+            }
+            _ => panic!("instructions.in:{}: malformed rule: {raw_line:?}", lineno + 1),
+        }
+    }
+
+    let generated = format!(
+        "/// Generated from `instructions.in` by `build.rs`. Do not hand-edit.\n\
+         pub const CYCLE_TABLE: [u8; 256] = {main_table:?};\n\
+         pub const CB_CYCLE_TABLE: [u8; 256] = {cb_table:?};\n"
+    );
+
+    let out_dir = env::var("OUT_DIR").unwrap();
+    let dest = Path::new(&out_dir).join("opcode_cycles_generated.rs");
+    fs::write(&dest, generated)
+        .unwrap_or_else(|e| panic!("failed to write {}: {e}", dest.display()));
+}
+
+fn select_table<'a>(
+    name: &str,
+    main_table: &'a mut [u8; 256],
+    cb_table: &'a mut [u8; 256],
+    lineno: usize,
+) -> &'a mut [u8; 256] {
+    match name {
+        "MAIN" => main_table,
+        "CB" => cb_table,
+        other => panic!("instructions.in:{}: unknown table {other:?}", lineno + 1),
+    }
+}
+
+fn parse_opcode(s: &str, lineno: usize) -> u8 {
+    let digits = s.strip_prefix("0x").unwrap_or_else(|| {
+        panic!("instructions.in:{}: opcode {s:?} must be hex (0x..)", lineno + 1)
+    });
+    u8::from_str_radix(digits, 16)
+        .unwrap_or_else(|e| panic!("instructions.in:{}: bad opcode {s:?}: {e}", lineno + 1))
+}
+
+fn parse_u8(s: &str, lineno: usize) -> u8 {
+    s.parse()
+        .unwrap_or_else(|e| panic!("instructions.in:{}: bad cycle count {s:?}: {e}", lineno + 1))
+}
@@ -0,0 +1,667 @@
+// Audio processing unit: two pulse channels, a wave channel, and a noise
+// channel, mixed through the master volume/panning registers and resampled
+// into a stereo f32 buffer a front-end drains -- the audio equivalent of how
+// `ppu.rs` turns hardware timing into a framebuffer.
+use super::save;
+use anyhow::Result;
+
+const APU_SAVE_STATE_VERSION: u8 = 1;
+const CPU_HZ: u32 = 4_194_304;
+const SAMPLE_RATE: u32 = 48_000;
+
+const DUTY_PATTERNS: [[u8; 8]; 4] = [
+    [0, 0, 0, 0, 0, 0, 0, 1], // 12.5%
+    [1, 0, 0, 0, 0, 0, 0, 1], // 25%
+    [1, 0, 0, 0, 0, 1, 1, 1], // 50%
+    [0, 1, 1, 1, 1, 1, 1, 0], // 75%
+];
+
+#[derive(Default, Clone, Copy)]
+struct LengthCounter {
+    value: u16,
+    enabled: bool,
+}
+
+impl LengthCounter {
+    fn tick(&mut self) -> bool {
+        // Returns true if the channel should keep playing.
+        if !self.enabled || self.value == 0 {
+            return true;
+        }
+        self.value -= 1;
+        self.value > 0
+    }
+}
+
+#[derive(Default, Clone, Copy)]
+struct Envelope {
+    initial_volume: u8,
+    add_mode: bool, // true = volume increases
+    period: u8,
+    volume: u8,
+    timer: u8,
+}
+
+impl Envelope {
+    fn trigger(&mut self, nrx2: u8) {
+        self.initial_volume = nrx2 >> 4;
+        self.add_mode = nrx2 & 0x08 != 0;
+        self.period = nrx2 & 0x07;
+        self.volume = self.initial_volume;
+        self.timer = self.period;
+    }
+
+    fn tick(&mut self) {
+        if self.period == 0 {
+            return;
+        }
+        if self.timer > 0 {
+            self.timer -= 1;
+        }
+        if self.timer == 0 {
+            self.timer = self.period;
+            if self.add_mode && self.volume < 15 {
+                self.volume += 1;
+            } else if !self.add_mode && self.volume > 0 {
+                self.volume -= 1;
+            }
+        }
+    }
+}
+
+#[derive(Default, Clone, Copy)]
+struct PulseChannel {
+    enabled: bool,
+    duty: u8,
+    duty_pos: u8,
+    freq: u16,
+    freq_timer: i32,
+    length: LengthCounter,
+    envelope: Envelope,
+    // Sweep (pulse 1 only); pulse 2 leaves these at their default no-op values.
+    sweep_period: u8,
+    sweep_negate: bool,
+    sweep_shift: u8,
+    sweep_timer: u8,
+    sweep_enabled: bool,
+    sweep_shadow_freq: u16,
+}
+
+impl PulseChannel {
+    fn trigger(&mut self, nrx1: u8, nrx2: u8, freq: u16, has_sweep: bool) {
+        self.enabled = true;
+        self.duty = nrx1 >> 6;
+        self.freq = freq;
+        self.freq_timer = (2048 - freq as i32) * 4;
+        if self.length.value == 0 {
+            self.length.value = 64 - (nrx1 & 0x3F) as u16;
+        }
+        self.envelope.trigger(nrx2);
+        if nrx2 >> 3 == 0 {
+            self.enabled = false; // DAC off (initial volume 0, no envelope increase)
+        }
+
+        if has_sweep {
+            self.sweep_shadow_freq = freq;
+            self.sweep_timer = if self.sweep_period == 0 { 8 } else { self.sweep_period };
+            self.sweep_enabled = self.sweep_period != 0 || self.sweep_shift != 0;
+            if self.sweep_shift != 0 {
+                self.sweep_calc();
+            }
+        }
+    }
+
+    fn sweep_calc(&mut self) -> u16 {
+        let delta = self.sweep_shadow_freq >> self.sweep_shift;
+        let new_freq = if self.sweep_negate {
+            self.sweep_shadow_freq.saturating_sub(delta)
+        } else {
+            self.sweep_shadow_freq + delta
+        };
+        if new_freq > 2047 {
+            self.enabled = false;
+        }
+        new_freq
+    }
+
+    fn tick_sweep(&mut self) {
+        if self.sweep_timer > 0 {
+            self.sweep_timer -= 1;
+        }
+        if self.sweep_timer == 0 {
+            self.sweep_timer = if self.sweep_period == 0 { 8 } else { self.sweep_period };
+            if self.sweep_enabled && self.sweep_period != 0 {
+                let new_freq = self.sweep_calc();
+                if new_freq <= 2047 && self.sweep_shift != 0 {
+                    self.sweep_shadow_freq = new_freq;
+                    self.freq = new_freq;
+                    self.sweep_calc(); // overflow re-check, matches real hardware
+                }
+            }
+        }
+    }
+
+    fn tick(&mut self, t_cycles: i32) {
+        self.freq_timer -= t_cycles;
+        while self.freq_timer <= 0 {
+            self.freq_timer += (2048 - self.freq as i32) * 4;
+            self.duty_pos = (self.duty_pos + 1) % 8;
+        }
+    }
+
+    fn amplitude(&self) -> f32 {
+        if !self.enabled {
+            return 0.0;
+        }
+        let bit = DUTY_PATTERNS[self.duty as usize][self.duty_pos as usize];
+        if bit == 0 {
+            0.0
+        } else {
+            (self.envelope.volume as f32) / 15.0
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct WaveChannel {
+    enabled: bool,
+    dac_on: bool,
+    freq: u16,
+    freq_timer: i32,
+    sample_pos: u8,
+    volume_shift: u8, // 0 = mute, 1 = 100%, 2 = 50%, 3 = 25%
+    length: LengthCounter,
+}
+
+impl Default for WaveChannel {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            dac_on: false,
+            freq: 0,
+            freq_timer: 0,
+            sample_pos: 0,
+            volume_shift: 0,
+            length: LengthCounter::default(),
+        }
+    }
+}
+
+impl WaveChannel {
+    fn trigger(&mut self, nr31: u8, freq: u16) {
+        self.enabled = self.dac_on;
+        self.freq = freq;
+        self.freq_timer = (2048 - freq as i32) * 2;
+        self.sample_pos = 0;
+        if self.length.value == 0 {
+            self.length.value = 256 - nr31 as u16;
+        }
+    }
+
+    fn tick(&mut self, t_cycles: i32, wave_ram: &[u8; 16]) -> u8 {
+        self.freq_timer -= t_cycles;
+        while self.freq_timer <= 0 {
+            self.freq_timer += (2048 - self.freq as i32) * 2;
+            self.sample_pos = (self.sample_pos + 1) % 32;
+        }
+        let byte = wave_ram[(self.sample_pos / 2) as usize];
+        if self.sample_pos % 2 == 0 {
+            byte >> 4
+        } else {
+            byte & 0x0F
+        }
+    }
+
+    fn amplitude(&self, raw_sample: u8) -> f32 {
+        if !self.enabled || self.volume_shift == 0 {
+            return 0.0;
+        }
+        let shifted = raw_sample >> (self.volume_shift - 1);
+        (shifted as f32) / 15.0
+    }
+}
+
+#[derive(Default, Clone, Copy)]
+struct NoiseChannel {
+    enabled: bool,
+    lfsr: u16,
+    freq_timer: i32,
+    divisor_code: u8,
+    shift: u8,
+    width_mode: bool, // true = 7-bit LFSR
+    length: LengthCounter,
+    envelope: Envelope,
+}
+
+impl NoiseChannel {
+    fn divisor(code: u8) -> i32 {
+        match code {
+            0 => 8,
+            n => (n as i32) * 16,
+        }
+    }
+
+    fn trigger(&mut self, nr41: u8, nr42: u8) {
+        self.enabled = true;
+        self.lfsr = 0x7FFF;
+        self.freq_timer = Self::divisor(self.divisor_code) << self.shift;
+        if self.length.value == 0 {
+            self.length.value = 64 - (nr41 & 0x3F) as u16;
+        }
+        self.envelope.trigger(nr42);
+        if nr42 >> 3 == 0 {
+            self.enabled = false;
+        }
+    }
+
+    fn tick(&mut self, t_cycles: i32) {
+        self.freq_timer -= t_cycles;
+        while self.freq_timer <= 0 {
+            self.freq_timer += Self::divisor(self.divisor_code) << self.shift;
+            let xor = (self.lfsr & 1) ^ ((self.lfsr >> 1) & 1);
+            self.lfsr >>= 1;
+            self.lfsr |= xor << 14;
+            if self.width_mode {
+                self.lfsr &= !(1 << 6);
+                self.lfsr |= xor << 6;
+            }
+        }
+    }
+
+    fn amplitude(&self) -> f32 {
+        if !self.enabled || self.lfsr & 1 != 0 {
+            0.0
+        } else {
+            (self.envelope.volume as f32) / 15.0
+        }
+    }
+}
+
+pub struct Apu {
+    nr10: u8,
+    nr11: u8,
+    nr12: u8,
+    nr13: u8,
+    nr14: u8,
+    nr21: u8,
+    nr22: u8,
+    nr23: u8,
+    nr24: u8,
+    nr30: u8,
+    nr31: u8,
+    nr32: u8,
+    nr33: u8,
+    nr34: u8,
+    wave_ram: [u8; 16],
+    nr41: u8,
+    nr42: u8,
+    nr43: u8,
+    nr44: u8,
+    nr50: u8,
+    nr51: u8,
+    power: bool, // NR52 bit 7
+
+    pulse1: PulseChannel,
+    pulse2: PulseChannel,
+    wave: WaveChannel,
+    noise: NoiseChannel,
+
+    frame_seq_cycles: u32, // counts T-cycles toward the next 512 Hz step
+    frame_seq_step: u8,    // 0..8
+
+    resample_cycles: u32, // counts T-cycles toward the next output sample
+    sample_buffer: Vec<f32>, // interleaved stereo, drained by the front-end
+}
+
+impl Apu {
+    pub fn new() -> Self {
+        Self {
+            nr10: 0,
+            nr11: 0,
+            nr12: 0,
+            nr13: 0,
+            nr14: 0,
+            nr21: 0,
+            nr22: 0,
+            nr23: 0,
+            nr24: 0,
+            nr30: 0,
+            nr31: 0,
+            nr32: 0,
+            nr33: 0,
+            nr34: 0,
+            wave_ram: [0; 16],
+            nr41: 0,
+            nr42: 0,
+            nr43: 0,
+            nr44: 0,
+            nr50: 0,
+            nr51: 0,
+            power: true,
+            pulse1: PulseChannel::default(),
+            pulse2: PulseChannel::default(),
+            wave: WaveChannel::default(),
+            noise: NoiseChannel::default(),
+            frame_seq_cycles: 0,
+            frame_seq_step: 0,
+            resample_cycles: 0,
+            sample_buffer: Vec::new(),
+        }
+    }
+
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0xFF10 => self.nr10 | 0x80,
+            0xFF11 => self.nr11 | 0x3F,
+            0xFF12 => self.nr12,
+            0xFF13 => 0xFF,
+            0xFF14 => self.nr14 | 0xBF,
+            0xFF16 => self.nr21 | 0x3F,
+            0xFF17 => self.nr22,
+            0xFF18 => 0xFF,
+            0xFF19 => self.nr24 | 0xBF,
+            0xFF1A => self.nr30 | 0x7F,
+            0xFF1B => 0xFF,
+            0xFF1C => self.nr32 | 0x9F,
+            0xFF1D => 0xFF,
+            0xFF1E => self.nr34 | 0xBF,
+            0xFF20 => self.nr41 | 0xC0,
+            0xFF21 => self.nr42,
+            0xFF22 => self.nr43,
+            0xFF23 => self.nr44 | 0xBF,
+            0xFF24 => self.nr50,
+            0xFF25 => self.nr51,
+            0xFF26 => {
+                (self.power as u8) << 7
+                    | 0x70
+                    | (self.noise.enabled as u8) << 3
+                    | (self.wave.enabled as u8) << 2
+                    | (self.pulse2.enabled as u8) << 1
+                    | (self.pulse1.enabled as u8)
+            }
+            0xFF30..=0xFF3F => self.wave_ram[(addr - 0xFF30) as usize],
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, v: u8) {
+        // Wave RAM and NR52 stay writable even while powered off.
+        if !self.power && !matches!(addr, 0xFF26 | 0xFF30..=0xFF3F) {
+            return;
+        }
+
+        match addr {
+            0xFF10 => {
+                self.nr10 = v;
+                self.pulse1.sweep_period = (v >> 4) & 0x07;
+                self.pulse1.sweep_negate = v & 0x08 != 0;
+                self.pulse1.sweep_shift = v & 0x07;
+            }
+            0xFF11 => self.nr11 = v,
+            0xFF12 => self.nr12 = v,
+            0xFF13 => self.nr13 = v,
+            0xFF14 => {
+                self.nr14 = v;
+                if v & 0x40 != 0 {
+                    self.pulse1.length.enabled = true;
+                }
+                if v & 0x80 != 0 {
+                    let freq = self.nr13 as u16 | ((v as u16 & 0x07) << 8);
+                    self.pulse1.trigger(self.nr11, self.nr12, freq, true);
+                }
+            }
+            0xFF16 => self.nr21 = v,
+            0xFF17 => self.nr22 = v,
+            0xFF18 => self.nr23 = v,
+            0xFF19 => {
+                self.nr24 = v;
+                if v & 0x40 != 0 {
+                    self.pulse2.length.enabled = true;
+                }
+                if v & 0x80 != 0 {
+                    let freq = self.nr23 as u16 | ((v as u16 & 0x07) << 8);
+                    self.pulse2.trigger(self.nr21, self.nr22, freq, false);
+                }
+            }
+            0xFF1A => {
+                self.nr30 = v;
+                self.wave.dac_on = v & 0x80 != 0;
+                if !self.wave.dac_on {
+                    self.wave.enabled = false;
+                }
+            }
+            0xFF1B => self.nr31 = v,
+            0xFF1C => {
+                self.nr32 = v;
+                self.wave.volume_shift = (v >> 5) & 0x03;
+            }
+            0xFF1D => self.nr33 = v,
+            0xFF1E => {
+                self.nr34 = v;
+                if v & 0x40 != 0 {
+                    self.wave.length.enabled = true;
+                }
+                if v & 0x80 != 0 {
+                    let freq = self.nr33 as u16 | ((v as u16 & 0x07) << 8);
+                    self.wave.trigger(self.nr31, freq);
+                }
+            }
+            0xFF20 => self.nr41 = v,
+            0xFF21 => self.nr42 = v,
+            0xFF22 => {
+                self.nr43 = v;
+                self.noise.divisor_code = v & 0x07;
+                self.noise.shift = v >> 4;
+                self.noise.width_mode = v & 0x08 != 0;
+            }
+            0xFF23 => {
+                self.nr44 = v;
+                if v & 0x40 != 0 {
+                    self.noise.length.enabled = true;
+                }
+                if v & 0x80 != 0 {
+                    self.noise.trigger(self.nr41, self.nr42);
+                }
+            }
+            0xFF24 => self.nr50 = v,
+            0xFF25 => self.nr51 = v,
+            0xFF26 => {
+                self.power = v & 0x80 != 0;
+                if !self.power {
+                    *self = Self { wave_ram: self.wave_ram, power: false, ..Self::new() };
+                }
+            }
+            0xFF30..=0xFF3F => self.wave_ram[(addr - 0xFF30) as usize] = v,
+            _ => {}
+        }
+    }
+
+    /// Advance every channel and the 512 Hz frame sequencer by `cycles`
+    /// T-cycles, appending newly-resampled stereo frames to the output
+    /// buffer. `main.rs` drains that buffer into the host audio stream.
+    pub fn tick(&mut self, cycles: u8) {
+        let t_cycles = cycles as i32;
+
+        self.pulse1.tick(t_cycles);
+        self.pulse2.tick(t_cycles);
+        let wave_raw = self.wave.tick(t_cycles, &self.wave_ram);
+        self.noise.tick(t_cycles);
+
+        self.frame_seq_cycles += cycles as u32;
+        const FRAME_SEQ_PERIOD: u32 = CPU_HZ / 512;
+        while self.frame_seq_cycles >= FRAME_SEQ_PERIOD {
+            self.frame_seq_cycles -= FRAME_SEQ_PERIOD;
+            self.clock_frame_sequencer();
+        }
+
+        self.resample_cycles += cycles as u32;
+        let resample_period = CPU_HZ / SAMPLE_RATE;
+        while self.resample_cycles >= resample_period {
+            self.resample_cycles -= resample_period;
+            self.push_sample(wave_raw);
+        }
+    }
+
+    fn clock_frame_sequencer(&mut self) {
+        // Length counters at 256 Hz (even steps), sweep at 128 Hz (2 and 6),
+        // envelopes at 64 Hz (step 7).
+        if self.frame_seq_step % 2 == 0 {
+            if !self.pulse1.length.tick() {
+                self.pulse1.enabled = false;
+            }
+            if !self.pulse2.length.tick() {
+                self.pulse2.enabled = false;
+            }
+            if !self.wave.length.tick() {
+                self.wave.enabled = false;
+            }
+            if !self.noise.length.tick() {
+                self.noise.enabled = false;
+            }
+        }
+        if self.frame_seq_step == 2 || self.frame_seq_step == 6 {
+            self.pulse1.tick_sweep();
+        }
+        if self.frame_seq_step == 7 {
+            self.pulse1.envelope.tick();
+            self.pulse2.envelope.tick();
+            self.noise.envelope.tick();
+        }
+        self.frame_seq_step = (self.frame_seq_step + 1) % 8;
+    }
+
+    fn push_sample(&mut self, wave_raw: u8) {
+        let p1 = self.pulse1.amplitude();
+        let p2 = self.pulse2.amplitude();
+        let wv = self.wave.amplitude(wave_raw);
+        let ns = self.noise.amplitude();
+
+        let left_vol = ((self.nr50 >> 4) & 0x07) as f32 / 7.0;
+        let right_vol = (self.nr50 & 0x07) as f32 / 7.0;
+
+        let mut left = 0.0f32;
+        let mut right = 0.0f32;
+        let pan = self.nr51;
+        let channels = [(p1, 0), (p2, 1), (wv, 2), (ns, 3)];
+        for (amp, idx) in channels {
+            if pan & (1 << (4 + idx)) != 0 {
+                left += amp;
+            }
+            if pan & (1 << idx) != 0 {
+                right += amp;
+            }
+        }
+
+        // 4 channels max, each in [0,1]; average so the mix doesn't clip.
+        left = (left / 4.0) * left_vol;
+        right = (right / 4.0) * right_vol;
+
+        self.sample_buffer.push(left);
+        self.sample_buffer.push(right);
+    }
+
+    /// Take every sample generated since the last call, for the host audio
+    /// stream to play back.
+    pub fn drain_samples(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.sample_buffer)
+    }
+
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut w = save::Writer::new();
+        w.u8(APU_SAVE_STATE_VERSION);
+        w.u8(self.nr10);
+        w.u8(self.nr11);
+        w.u8(self.nr12);
+        w.u8(self.nr13);
+        w.u8(self.nr14);
+        w.u8(self.nr21);
+        w.u8(self.nr22);
+        w.u8(self.nr23);
+        w.u8(self.nr24);
+        w.u8(self.nr30);
+        w.u8(self.nr31);
+        w.u8(self.nr32);
+        w.u8(self.nr33);
+        w.u8(self.nr34);
+        w.bytes(&self.wave_ram);
+        w.u8(self.nr41);
+        w.u8(self.nr42);
+        w.u8(self.nr43);
+        w.u8(self.nr44);
+        w.u8(self.nr50);
+        w.u8(self.nr51);
+        w.bool(self.power);
+        w.into_vec()
+    }
+
+    pub fn load_state(&mut self, data: &[u8]) -> Result<()> {
+        let mut r = save::Reader::new(data);
+        r.expect_version(APU_SAVE_STATE_VERSION, "APU")?;
+        self.nr10 = r.u8()?;
+        self.nr11 = r.u8()?;
+        self.nr12 = r.u8()?;
+        self.nr13 = r.u8()?;
+        self.nr14 = r.u8()?;
+        self.nr21 = r.u8()?;
+        self.nr22 = r.u8()?;
+        self.nr23 = r.u8()?;
+        self.nr24 = r.u8()?;
+        self.nr30 = r.u8()?;
+        self.nr31 = r.u8()?;
+        self.nr32 = r.u8()?;
+        self.nr33 = r.u8()?;
+        self.nr34 = r.u8()?;
+        r.bytes_into(&mut self.wave_ram)?;
+        self.nr41 = r.u8()?;
+        self.nr42 = r.u8()?;
+        self.nr43 = r.u8()?;
+        self.nr44 = r.u8()?;
+        self.nr50 = r.u8()?;
+        self.nr51 = r.u8()?;
+        self.power = r.bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn triggered_pulse1(apu: &mut Apu) {
+        apu.write(0xFF10, 0x00); // no sweep
+        apu.write(0xFF11, 0x80); // duty 2 (50%), length load 0
+        apu.write(0xFF12, 0xF0); // initial volume 15, no envelope, DAC on
+        apu.write(0xFF13, 0xFF); // freq low byte
+        apu.write(0xFF14, 0x87); // freq high bits + trigger
+    }
+
+    #[test]
+    fn pulse_channel_duty_pattern_drives_amplitude_as_it_steps() {
+        let mut apu = Apu::new();
+        triggered_pulse1(&mut apu);
+
+        // Duty 2 is [1,0,0,0,0,1,1,1]; freq 0x7FF makes freq_timer == 4, so
+        // one tick(4) advances duty_pos by exactly one step.
+        assert_eq!(apu.pulse1.amplitude(), 1.0); // duty_pos 0 -> bit 1
+        apu.pulse1.tick(4);
+        assert_eq!(apu.pulse1.amplitude(), 0.0); // duty_pos 1 -> bit 0
+        apu.pulse1.tick(4);
+        apu.pulse1.tick(4);
+        apu.pulse1.tick(4);
+        assert_eq!(apu.pulse1.amplitude(), 0.0); // duty_pos 4 -> bit 0
+        apu.pulse1.tick(4);
+        assert_eq!(apu.pulse1.amplitude(), 1.0); // duty_pos 5 -> bit 1
+    }
+
+    #[test]
+    fn mixer_routes_channel_to_only_the_panned_side() {
+        let mut apu = Apu::new();
+        triggered_pulse1(&mut apu);
+        apu.write(0xFF24, 0x77); // max volume both sides
+        apu.write(0xFF25, 0x01); // pulse1 -> right channel only
+
+        apu.push_sample(0);
+
+        assert_eq!(apu.sample_buffer, vec![0.0, 0.25]); // [left, right]
+    }
+}
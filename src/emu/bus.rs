@@ -1,5 +1,55 @@
-// bus placeholder
-use super::{cart::Cartridge, ppu::Ppu};
+use super::{
+    apu::Apu,
+    cart::Cartridge,
+    device::{InterruptFlagDevice, JoypadDevice, MmioDevice, SerialDevice, TimerDevice},
+    ppu::Ppu,
+    save,
+};
+use anyhow::Result;
+
+const BUS_SAVE_STATE_VERSION: u8 = 5; // v5 adds cartridge banking/RAM/RTC state
+
+/// What the PPU's sprite fetch sees while a DMA transfer owns OAM -- real
+/// hardware's sprite fetcher can't see OAM either during the transfer.
+const BLOCKED_OAM: [u8; 0x00A0] = [0xFF; 0x00A0];
+
+/// `0xFF46` - OAM DMA. Copies one byte per machine cycle instead of all 160
+/// at once, so games that kick off a transfer and then busy-wait in HRAM
+/// see it actually take the ~160 M-cycles real hardware takes.
+#[derive(Default)]
+struct OamDma {
+    active: bool,
+    source_base: u16,
+    remaining: u8, // bytes left to copy, countdown from 0xA0
+    cycle_acc: u8, // T-cycles banked toward the next byte
+}
+
+impl OamDma {
+    fn start(&mut self, v: u8) {
+        self.active = true;
+        self.source_base = (v as u16) << 8;
+        self.remaining = 0xA0;
+        self.cycle_acc = 0;
+    }
+
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut w = save::Writer::new();
+        w.bool(self.active);
+        w.u16(self.source_base);
+        w.u8(self.remaining);
+        w.u8(self.cycle_acc);
+        w.into_vec()
+    }
+
+    pub fn load_state(&mut self, data: &[u8]) -> Result<()> {
+        let mut r = save::Reader::new(data);
+        self.active = r.bool()?;
+        self.source_base = r.u16()?;
+        self.remaining = r.u8()?;
+        self.cycle_acc = r.u8()?;
+        Ok(())
+    }
+}
 
 #[derive(Default, Copy, Clone)]
 pub struct JoypadState {
@@ -20,18 +70,20 @@ pub struct Bus {
     pub vram: [u8; 0x2000], // 8000-9FFF
     pub oam: [u8; 0x00A0],  // FE00-FE9F
     pub ppu: Ppu,
-    pub joypad: JoypadState,
-    joyp_select: u8, // bits 4/5 of P1
+    pub apu: Apu,
+
+    // Memory-mapped devices. Each owns its own registers; `step` ticks the
+    // timer and serial devices (joypad and IF have no timing of their own);
+    // `read8`/`write8` dispatch to the one that owns the
+    // address instead of matching every register inline.
+    joypad_dev: JoypadDevice,
+    serial_dev: SerialDevice,
+    timer_dev: TimerDevice,
+    if_dev: InterruptFlagDevice,
+
+    dma: OamDma,
 
-    // TODO: add timer, interrupt flags, IE, IF, etc.
     pub ie: u8, // FFFF
-    pub iflag: u8, // FF0F
-    // Timers
-    div: u16,
-    tima: u8,
-    tma: u8,
-    tac: u8,
-    timer_counter: u32,
 }
 
 impl Bus {
@@ -43,40 +95,114 @@ impl Bus {
             vram: [0; 0x2000],
             oam: [0; 0x00A0],
             ppu: Ppu::new(),
-            joypad: JoypadState::default(),
-            joyp_select: 0x00,
+            apu: Apu::new(),
+            joypad_dev: JoypadDevice::new(),
+            serial_dev: SerialDevice::new(),
+            timer_dev: TimerDevice::new(),
+            if_dev: InterruptFlagDevice::new(),
+            dma: OamDma::default(),
             ie: 0,
-            iflag: 0,
-            div: 0,
-            tima: 0,
-            tma: 0,
-            tac: 0,
-            timer_counter: 0,
+        }
+    }
+
+    /// `IF` as seen by the CPU's interrupt dispatch.
+    pub fn iflag(&self) -> u8 {
+        self.if_dev.get()
+    }
+
+    pub fn set_iflag(&mut self, v: u8) {
+        self.if_dev.set(v);
+    }
+
+    pub fn clear_iflag_bit(&mut self, bit: u8) {
+        self.if_dev.clear(bit);
+    }
+
+    pub fn set_joypad(&mut self, s: JoypadState) {
+        self.joypad_dev.set_state(s);
+        if self.joypad_dev.take_pending_interrupt() {
+            self.if_dev.request(4); // Joypad
         }
     }
 
     pub fn step(&mut self, cycles: u8) {
+        self.step_dma(cycles);
+
         let vram = &self.vram;
-        let (vblank, stat_irq) = self.ppu.step(cycles as u32, vram);
+        let oam_for_ppu: &[u8] = if self.dma.active { &BLOCKED_OAM } else { &self.oam };
+        let (vblank, stat_irq) = self.ppu.step(cycles as u32, vram, oam_for_ppu);
         if vblank {
-            // Set VBlank interrupt
-            self.iflag |= 0x01;
+            self.if_dev.request(0); // VBlank
         }
         if stat_irq {
-            self.iflag |= 0x02;
+            self.if_dev.request(1); // LCD STAT
+        }
+
+        self.timer_dev.tick(cycles);
+        if self.timer_dev.take_pending_interrupt() {
+            self.if_dev.request(2); // Timer
+        }
+
+        self.serial_dev.tick(cycles);
+        if self.serial_dev.take_pending_interrupt() {
+            self.if_dev.request(3); // Serial
+        }
+
+        self.apu.tick(cycles);
+    }
+
+    /// Take every audio sample generated since the last call.
+    pub fn drain_audio_samples(&mut self) -> Vec<f32> {
+        self.apu.drain_samples()
+    }
+
+    /// Take every byte shifted out over the serial port since the last
+    /// call, for a host-side capture mode to print.
+    pub fn drain_serial_output(&mut self) -> Vec<u8> {
+        self.serial_dev.take_output()
+    }
+
+    /// Copy one byte per machine cycle while a DMA transfer is active,
+    /// mirroring the real ~160 M-cycle transfer time.
+    fn step_dma(&mut self, cycles: u8) {
+        if !self.dma.active {
+            return;
+        }
+        self.dma.cycle_acc += cycles;
+        while self.dma.cycle_acc >= 4 && self.dma.remaining > 0 {
+            self.dma.cycle_acc -= 4;
+            let index = 0xA0 - self.dma.remaining;
+            let src = self.dma.source_base + index as u16;
+            self.oam[index as usize] = self.read8_raw(src);
+            self.dma.remaining -= 1;
+        }
+        if self.dma.remaining == 0 {
+            self.dma.active = false;
         }
-        self.tick_timer(cycles as u32);
-        // TODO: APU stepping
     }
 
     pub fn read8(&self, addr: u16) -> u8 {
+        // While a transfer is active, the CPU can only see HRAM -- the bus
+        // is busy feeding OAM from `self.dma.source_base` instead.
+        if self.dma.active && !(0xFF80..=0xFFFE).contains(&addr) {
+            return 0xFF;
+        }
+        self.read8_raw(addr)
+    }
+
+    fn read8_raw(&self, addr: u16) -> u8 {
         match addr {
-            0x0000..=0x7FFF => self.cart.read(addr),          // ROM (no MBC yet)
+            0x0000..=0x7FFF => self.cart.read(addr), // ROM, banked by the cart's MBC
             0x8000..=0x9FFF => self.vram[(addr - 0x8000) as usize],
+            0xA000..=0xBFFF => self.cart.read_ram(addr),
             0xC000..=0xDFFF => self.wram[(addr - 0xC000) as usize],
             0xE000..=0xFDFF => self.wram[(addr - 0xE000) as usize], // echo RAM
             0xFE00..=0xFE9F => self.oam[(addr - 0xFE00) as usize],
-            0xFF00 => self.read_joyp(),
+            0xFF00 => self.joypad_dev.read(addr),
+            0xFF01 | 0xFF02 => self.serial_dev.read(addr),
+            0xFF04..=0xFF07 => self.timer_dev.read(addr),
+            0xFF0F => self.if_dev.read(addr),
+            0xFF10..=0xFF26 | 0xFF30..=0xFF3F => self.apu.read(addr),
             0xFF40 => self.ppu.lcdc,
             0xFF41 => self.ppu.stat,
             0xFF42 => self.ppu.scy,
@@ -84,26 +210,45 @@ impl Bus {
             0xFF44 => self.ppu.ly,
             0xFF45 => self.ppu.lyc,
             0xFF47 => self.ppu.bgp,
+            0xFF48 => self.ppu.obp0,
+            0xFF49 => self.ppu.obp1,
             0xFF4A => self.ppu.wy,
             0xFF4B => self.ppu.wx,
-            0xFF04 => (self.div >> 8) as u8,
-            0xFF05 => self.tima,
-            0xFF06 => self.tma,
-            0xFF07 => self.tac | 0xF8,
             0xFF80..=0xFFFE => self.hram[(addr - 0xFF80) as usize],
-            0xFF0F => self.iflag,
             0xFFFF => self.ie,
-            _ => 0xFF, // TODO: map VRAM/OAM/IO
+            _ => 0xFF, // TODO: map remaining IO
         }
     }
 
     pub fn write8(&mut self, addr: u16, v: u8) {
+        // Mirrors `read8`'s lockout: while a transfer is active the bus is
+        // busy feeding OAM, so the CPU can't land writes anywhere except
+        // HRAM either -- except `0xFF46` itself, which still works (it's
+        // how real hardware lets a ROM retrigger/redirect the transfer).
+        if self.dma.active && addr != 0xFF46 && !(0xFF80..=0xFFFE).contains(&addr) {
+            return;
+        }
+        self.write8_raw(addr, v);
+    }
+
+    fn write8_raw(&mut self, addr: u16, v: u8) {
         match addr {
+            0x0000..=0x7FFF => self.cart.write(addr, v), // MBC banking registers
             0x8000..=0x9FFF => self.vram[(addr - 0x8000) as usize] = v,
+            0xA000..=0xBFFF => self.cart.write_ram(addr, v),
             0xC000..=0xDFFF => self.wram[(addr - 0xC000) as usize] = v,
             0xE000..=0xFDFF => self.wram[(addr - 0xE000) as usize] = v, // echo RAM
             0xFE00..=0xFE9F => self.oam[(addr - 0xFE00) as usize] = v,
-            0xFF00 => self.joyp_select = v & 0x30,
+            0xFF00 => {
+                self.joypad_dev.write(addr, v);
+                if self.joypad_dev.take_pending_interrupt() {
+                    self.if_dev.request(4); // Joypad
+                }
+            }
+            0xFF01 | 0xFF02 => self.serial_dev.write(addr, v),
+            0xFF04..=0xFF07 => self.timer_dev.write(addr, v),
+            0xFF0F => self.if_dev.write(addr, v),
+            0xFF10..=0xFF26 | 0xFF30..=0xFF3F => self.apu.write(addr, v),
             0xFF40 => self.ppu.lcdc = v,
             0xFF41 => self.ppu.stat = (self.ppu.stat & 0x07) | (v & 0x78), // only interrupt bits writable
             0xFF42 => self.ppu.scy = v,
@@ -111,79 +256,190 @@ impl Bus {
             0xFF44 => self.ppu.ly = 0, // writing resets LY
             0xFF45 => self.ppu.lyc = v,
             0xFF47 => self.ppu.bgp = v,
+            0xFF48 => self.ppu.obp0 = v,
+            0xFF49 => self.ppu.obp1 = v,
             0xFF4A => self.ppu.wy = v,
             0xFF4B => self.ppu.wx = v,
-            0xFF04 => self.div = 0,
-            0xFF05 => self.tima = v,
-            0xFF06 => self.tma = v,
-            0xFF07 => self.tac = v & 0x07,
             0xFF80..=0xFFFE => self.hram[(addr - 0xFF80) as usize] = v,
-            0xFF0F => self.iflag = v,
             0xFFFF => self.ie = v,
+            0xFF46 => self.dma.start(v),
             _ => {
-                if addr == 0xFF46 {
-                    let base = (v as u16) << 8;
-                    for i in 0..0xA0 {
-                        let data = self.read8(base + i);
-                        self.oam[i as usize] = data;
-                    }
-                }
-                // TODO: map VRAM/OAM/IO/MBC registers
+                // TODO: map remaining IO/MBC registers
             }
         }
     }
 
-    fn read_joyp(&self) -> u8 {
-        // Bit = 0 means selected/pressed. Unselected lines return 1.
-        let mut res = 0xCF | (self.joyp_select & 0x30);
-        let sel_dpad = self.joyp_select & 0x10 == 0;
-        let sel_btn = self.joyp_select & 0x20 == 0;
+    /// Serialize WRAM/HRAM/VRAM/OAM, the device registers, the cartridge's
+    /// banking/RAM/RTC state, and the nested PPU/APU state. Cartridge ROM
+    /// isn't included since it never changes after load.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut w = save::Writer::new();
+        w.u8(BUS_SAVE_STATE_VERSION);
+        w.bytes(&self.wram);
+        w.bytes(&self.hram);
+        w.bytes(&self.vram);
+        w.bytes(&self.oam);
+        w.u8(self.ie);
 
-        if sel_dpad {
-            if self.joypad.right { res &= !0x01; }
-            if self.joypad.left { res &= !0x02; }
-            if self.joypad.up { res &= !0x04; }
-            if self.joypad.down { res &= !0x08; }
-        }
+        let cart_state = self.cart.save_state();
+        w.u32(cart_state.len() as u32);
+        w.bytes(&cart_state);
 
-        if sel_btn {
-            if self.joypad.a { res &= !0x01; }
-            if self.joypad.b { res &= !0x02; }
-            if self.joypad.select { res &= !0x04; }
-            if self.joypad.start { res &= !0x08; }
-        }
+        let if_state = self.if_dev.save_state();
+        w.u32(if_state.len() as u32);
+        w.bytes(&if_state);
+
+        let joypad_state = self.joypad_dev.save_state();
+        w.u32(joypad_state.len() as u32);
+        w.bytes(&joypad_state);
 
-        res
+        let serial_state = self.serial_dev.save_state();
+        w.u32(serial_state.len() as u32);
+        w.bytes(&serial_state);
+
+        let timer_state = self.timer_dev.save_state();
+        w.u32(timer_state.len() as u32);
+        w.bytes(&timer_state);
+
+        let apu_state = self.apu.save_state();
+        w.u32(apu_state.len() as u32);
+        w.bytes(&apu_state);
+
+        let dma_state = self.dma.save_state();
+        w.u32(dma_state.len() as u32);
+        w.bytes(&dma_state);
+
+        w.bytes(&self.ppu.save_state());
+        w.into_vec()
     }
 
-    fn timer_freq_divider(&self) -> u32 {
-        match self.tac & 0x03 {
-            0 => 1024, // 4096 Hz
-            1 => 16,   // 262144 Hz
-            2 => 64,   // 65536 Hz
-            _ => 256,  // 16384 Hz
-        }
+    pub fn load_state(&mut self, data: &[u8]) -> Result<()> {
+        let mut r = save::Reader::new(data);
+        r.expect_version(BUS_SAVE_STATE_VERSION, "bus")?;
+        r.bytes_into(&mut self.wram)?;
+        r.bytes_into(&mut self.hram)?;
+        r.bytes_into(&mut self.vram)?;
+        r.bytes_into(&mut self.oam)?;
+        self.ie = r.u8()?;
+
+        let cart_len = r.u32()? as usize;
+        self.cart.load_state(r.bytes(cart_len)?)?;
+
+        let if_len = r.u32()? as usize;
+        self.if_dev.load_state(r.bytes(if_len)?)?;
+
+        let joypad_len = r.u32()? as usize;
+        self.joypad_dev.load_state(r.bytes(joypad_len)?)?;
+
+        let serial_len = r.u32()? as usize;
+        self.serial_dev.load_state(r.bytes(serial_len)?)?;
+
+        let timer_len = r.u32()? as usize;
+        self.timer_dev.load_state(r.bytes(timer_len)?)?;
+
+        let apu_len = r.u32()? as usize;
+        self.apu.load_state(r.bytes(apu_len)?)?;
+
+        let dma_len = r.u32()? as usize;
+        self.dma.load_state(r.bytes(dma_len)?)?;
+
+        self.ppu.load_state(r.rest())?;
+        Ok(())
     }
+}
 
-    fn tick_timer(&mut self, cycles: u32) {
-        // DIV increments at 16384 Hz: +4 per CPU cycle on the upper byte.
-        self.div = self.div.wrapping_add((cycles * 4) as u16);
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        if self.tac & 0x04 == 0 {
-            return;
+    fn test_bus() -> Bus {
+        Bus::new(Cartridge::blank(0x10000))
+    }
+
+    #[test]
+    fn oam_dma_copies_one_byte_per_4_t_cycles_and_completes_after_160_bytes() {
+        let mut bus = test_bus();
+        for i in 0..0xA0u16 {
+            bus.write8(0xC000 + i, (i + 1) as u8);
         }
+        bus.write8(0xFF46, 0xC0); // source base 0xC000
 
-        self.timer_counter += cycles;
-        let period = self.timer_freq_divider();
-        while self.timer_counter >= period {
-            self.timer_counter -= period;
-            let (new, overflow) = self.tima.overflowing_add(1);
-            if overflow {
-                self.tima = self.tma;
-                self.iflag |= 0x04; // timer interrupt
-            } else {
-                self.tima = new;
-            }
+        bus.step(4);
+        assert_eq!(bus.oam[0], 1); // first byte already landed after one M-cycle
+        assert!(bus.dma.active);
+
+        for _ in 1..0xA0 {
+            bus.step(4);
         }
+        assert!(!bus.dma.active);
+        for i in 0..0xA0usize {
+            assert_eq!(bus.oam[i], (i + 1) as u8);
+        }
+    }
+
+    #[test]
+    fn writes_outside_hram_are_dropped_during_an_active_transfer_except_0xff46() {
+        let mut bus = test_bus();
+        bus.write8(0xC000, 0xAA);
+        bus.write8(0xFF46, 0xC0); // starts a transfer, dma.active becomes true
+
+        bus.write8(0xC000, 0xBB); // dropped -- the bus is busy feeding OAM
+        assert_eq!(bus.wram[0], 0xAA);
+
+        bus.write8(0xFF80, 0x11); // HRAM stays writable during a transfer
+        assert_eq!(bus.hram[0], 0x11);
+
+        bus.write8(0xFF46, 0x00); // retriggering the transfer itself still works
+        assert!(bus.dma.active);
+        assert_eq!(bus.dma.source_base, 0x0000);
+    }
+
+    #[test]
+    fn reads_outside_hram_return_0xff_during_an_active_transfer() {
+        let mut bus = test_bus();
+        bus.write8(0xC000, 0x42);
+        bus.write8(0xFF46, 0xC0);
+
+        assert_eq!(bus.read8(0xC000), 0xFF);
+        assert_eq!(bus.read8(0xFF80), 0x00); // HRAM unaffected
+    }
+
+    #[test]
+    fn save_state_round_trips_wram_oam_in_flight_dma_apu_and_serial_state() {
+        let mut bus = test_bus();
+        bus.wram[0] = 0xAB;
+        bus.oam[5] = 0xCD;
+        bus.ie = 0x1F;
+
+        bus.apu.write(0xFF24, 0x77); // NR50
+        bus.apu.write(0xFF11, 0x80); // NR11 duty bits
+
+        // Before the DMA starts: writes outside HRAM are dropped during a transfer.
+        bus.write8(0xFF01, 0x99); // SB
+        bus.write8(0xFF02, 0x81); // start an in-flight serial transfer
+
+        bus.write8(0xC001, 0x11);
+        bus.write8(0xFF46, 0xC0); // start a DMA transfer
+        bus.step(4); // advance partway, so remaining/cycle_acc aren't their defaults
+
+        let snapshot = bus.save_state();
+
+        let mut restored = test_bus();
+        restored.load_state(&snapshot).expect("a freshly-taken snapshot must load");
+
+        assert_eq!(restored.wram[0], 0xAB);
+        assert_eq!(restored.oam[5], 0xCD);
+        assert_eq!(restored.ie, 0x1F);
+        assert_eq!(restored.apu.read(0xFF24), 0x77);
+        assert_eq!(restored.apu.read(0xFF11) & 0xC0, 0x80);
+
+        assert!(restored.dma.active);
+        assert_eq!(restored.dma.source_base, bus.dma.source_base);
+        assert_eq!(restored.dma.remaining, bus.dma.remaining);
+        assert_eq!(restored.dma.cycle_acc, bus.dma.cycle_acc);
+
+        // Bypass the DMA read lockout (both busses are mid-transfer) to
+        // check the serial device's own state came back too.
+        assert_eq!(restored.read8_raw(0xFF01), 0x99);
     }
 }
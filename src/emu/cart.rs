@@ -1,10 +1,42 @@
-// cartridge loader placeholder
+// Cartridge loading and memory bank controller emulation.
+use super::save;
 use anyhow::{bail, Result};
 use std::fs;
+use std::path::PathBuf;
+use std::time::Instant;
+
+const CART_SAVE_STATE_VERSION: u8 = 1;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum MbcKind {
+    None,
+    Mbc1,
+    Mbc3,
+    Mbc5,
+}
 
 #[derive(Clone)]
 pub struct Cartridge {
     pub rom: Vec<u8>,
+    ram: Vec<u8>,
+    kind: MbcKind,
+    rom_bank_count: usize,
+
+    ram_enabled: bool,
+    rom_bank_lo: u8, // MBC1: 5 bits: MBC3: 7 bits; MBC5: low 8 bits
+    rom_bank_hi: u8, // MBC1: 2 bits (upper ROM bits / RAM bank); MBC5: bit 8
+    ram_bank: u8,
+    banking_mode: u8, // MBC1 only: 0 = ROM banking, 1 = RAM banking
+
+    // MBC3 real-time clock: seconds, minutes, hours, day-low, day-high
+    // (bit 0 = day bit 8, bit 6 = halt, bit 7 = day carry).
+    rtc: [u8; 5],
+    rtc_latched: [u8; 5],
+    rtc_latch_write: Option<u8>, // last byte written to 0x6000-0x7FFF, for the 0-then-1 latch sequence
+    rtc_started: Instant,
+
+    battery: bool,
+    sav_path: Option<PathBuf>,
 }
 
 impl Cartridge {
@@ -13,11 +45,359 @@ impl Cartridge {
         if rom.len() < 0x150 {
             bail!("ROM too small / invalid");
         }
-        Ok(Self { rom })
+
+        let (kind, battery) = match rom[0x0147] {
+            0x00 => (MbcKind::None, false),
+            0x01 | 0x02 => (MbcKind::Mbc1, false),
+            0x03 => (MbcKind::Mbc1, true),
+            0x0F | 0x10 | 0x13 => (MbcKind::Mbc3, true),
+            0x11 | 0x12 => (MbcKind::Mbc3, false),
+            0x19 | 0x1A => (MbcKind::Mbc5, false),
+            0x1B | 0x1E => (MbcKind::Mbc5, true),
+            0x1C | 0x1D => (MbcKind::Mbc5, false),
+            other => {
+                eprintln!("cart: unrecognized cartridge type 0x{other:02X}, treating as ROM-only");
+                (MbcKind::None, false)
+            }
+        };
+
+        let rom_bank_count = match rom[0x0148] {
+            n @ 0x00..=0x08 => 2usize << n,
+            other => {
+                eprintln!("cart: unrecognized ROM size byte 0x{other:02X}, assuming 2 banks");
+                2
+            }
+        };
+
+        let ram_size = match rom[0x0149] {
+            0x00 => 0,
+            0x01 => 0x800,  // 2 KiB (unofficial, rarely used)
+            0x02 => 0x2000, // 8 KiB, 1 bank
+            0x03 => 0x8000, // 32 KiB, 4 banks
+            0x04 => 0x20000, // 128 KiB, 16 banks
+            0x05 => 0x10000, // 64 KiB, 8 banks
+            other => {
+                eprintln!("cart: unrecognized RAM size byte 0x{other:02X}, assuming none");
+                0
+            }
+        };
+
+        let sav_path = if battery {
+            Some(PathBuf::from(path).with_extension("sav"))
+        } else {
+            None
+        };
+
+        let ram = match &sav_path {
+            Some(p) if p.exists() => {
+                let mut saved = fs::read(p)?;
+                saved.resize(ram_size, 0);
+                saved
+            }
+            _ => vec![0u8; ram_size],
+        };
+
+        Ok(Self {
+            rom,
+            ram,
+            kind,
+            rom_bank_count,
+            ram_enabled: false,
+            rom_bank_lo: 1,
+            rom_bank_hi: 0,
+            ram_bank: 0,
+            banking_mode: 0,
+            rtc: [0; 5],
+            rtc_latched: [0; 5],
+            rtc_latch_write: None,
+            rtc_started: Instant::now(),
+            battery,
+            sav_path,
+        })
+    }
+
+    /// A cartridge with no backing file, used by unit tests that just need
+    /// a `Bus` to drive CPU opcodes through -- no MBC, no battery.
+    #[cfg(test)]
+    pub(crate) fn blank(rom_size: usize) -> Self {
+        Self {
+            rom: vec![0u8; rom_size],
+            ram: Vec::new(),
+            kind: MbcKind::None,
+            rom_bank_count: 2,
+            ram_enabled: false,
+            rom_bank_lo: 1,
+            rom_bank_hi: 0,
+            ram_bank: 0,
+            banking_mode: 0,
+            rtc: [0; 5],
+            rtc_latched: [0; 5],
+            rtc_latch_write: None,
+            rtc_started: Instant::now(),
+            battery: false,
+            sav_path: None,
+        }
+    }
+
+    fn rom_bank(&self) -> usize {
+        let bank = match self.kind {
+            MbcKind::None => return 1,
+            MbcKind::Mbc1 => {
+                let lo = self.rom_bank_lo.max(1) as usize & 0x1F;
+                if self.banking_mode == 0 {
+                    lo | ((self.rom_bank_hi as usize & 0x03) << 5)
+                } else {
+                    lo
+                }
+            }
+            MbcKind::Mbc3 => self.rom_bank_lo.max(1) as usize & 0x7F,
+            MbcKind::Mbc5 => (self.rom_bank_lo as usize) | ((self.rom_bank_hi as usize & 0x01) << 8),
+        };
+        bank % self.rom_bank_count.max(1)
+    }
+
+    fn ram_bank(&self) -> usize {
+        match self.kind {
+            MbcKind::Mbc1 if self.banking_mode == 1 => self.rom_bank_hi as usize & 0x03,
+            _ => self.ram_bank as usize,
+        }
     }
 
     pub fn read(&self, addr: u16) -> u8 {
-        let i = addr as usize;
-        if i < self.rom.len() { self.rom[i] } else { 0xFF }
+        match addr {
+            0x0000..=0x3FFF => self.rom.get(addr as usize).copied().unwrap_or(0xFF),
+            0x4000..=0x7FFF => {
+                let offset = self.rom_bank() * 0x4000 + (addr as usize - 0x4000);
+                self.rom.get(offset).copied().unwrap_or(0xFF)
+            }
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, v: u8) {
+        match self.kind {
+            MbcKind::None => {}
+            MbcKind::Mbc1 => match addr {
+                0x0000..=0x1FFF => self.ram_enabled = v & 0x0F == 0x0A,
+                0x2000..=0x3FFF => self.rom_bank_lo = v & 0x1F,
+                0x4000..=0x5FFF => self.rom_bank_hi = v & 0x03,
+                0x6000..=0x7FFF => self.banking_mode = v & 0x01,
+                _ => {}
+            },
+            MbcKind::Mbc3 => match addr {
+                0x0000..=0x1FFF => self.ram_enabled = v & 0x0F == 0x0A,
+                0x2000..=0x3FFF => self.rom_bank_lo = v & 0x7F,
+                0x4000..=0x5FFF => self.ram_bank = v,
+                0x6000..=0x7FFF => {
+                    if self.rtc_latch_write == Some(0x00) && v == 0x01 {
+                        self.latch_rtc();
+                    }
+                    self.rtc_latch_write = Some(v);
+                }
+                _ => {}
+            },
+            MbcKind::Mbc5 => match addr {
+                0x0000..=0x1FFF => self.ram_enabled = v & 0x0F == 0x0A,
+                0x2000..=0x2FFF => self.rom_bank_lo = v,
+                0x3000..=0x3FFF => self.rom_bank_hi = v & 0x01,
+                0x4000..=0x5FFF => self.ram_bank = v & 0x0F,
+                _ => {}
+            },
+        }
+    }
+
+    /// Snapshot the running RTC into the latched registers MBC3 reads back
+    /// through the 0x08-0x0C RAM-bank-select values.
+    fn latch_rtc(&mut self) {
+        let elapsed = self.rtc_started.elapsed().as_secs();
+        let days = elapsed / 86400;
+        let secs_today = elapsed % 86400;
+        self.rtc[0] = (secs_today % 60) as u8;
+        self.rtc[1] = ((secs_today / 60) % 60) as u8;
+        self.rtc[2] = (secs_today / 3600) as u8;
+        self.rtc[3] = (days & 0xFF) as u8;
+        let day_high_bit = ((days >> 8) & 0x01) as u8;
+        let overflow = if days > 0x1FF { 0x80 } else { 0x00 };
+        self.rtc[4] = day_high_bit | overflow;
+        self.rtc_latched = self.rtc;
+    }
+
+    pub fn read_ram(&self, addr: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+        if self.kind == MbcKind::Mbc3 && (0x08..=0x0C).contains(&self.ram_bank) {
+            return self.rtc_latched[(self.ram_bank - 0x08) as usize];
+        }
+        let offset = self.ram_bank() * 0x2000 + (addr as usize - 0xA000);
+        self.ram.get(offset).copied().unwrap_or(0xFF)
+    }
+
+    pub fn write_ram(&mut self, addr: u16, v: u8) {
+        if !self.ram_enabled {
+            return;
+        }
+        if self.kind == MbcKind::Mbc3 && (0x08..=0x0C).contains(&self.ram_bank) {
+            self.rtc[(self.ram_bank - 0x08) as usize] = v;
+            return;
+        }
+        let offset = self.ram_bank() * 0x2000 + (addr as usize - 0xA000);
+        if let Some(slot) = self.ram.get_mut(offset) {
+            *slot = v;
+        }
+    }
+
+    /// Persist battery-backed RAM to the `.sav` file next to the ROM.
+    /// No-op for carts without a battery.
+    pub fn save_ram(&self) -> Result<()> {
+        if let Some(path) = &self.sav_path {
+            fs::write(path, &self.ram)?;
+        }
+        Ok(())
+    }
+
+    /// Serialize banking state, cart RAM contents, and the MBC3 RTC
+    /// registers. ROM and `kind`/`rom_bank_count` aren't included -- they're
+    /// fixed by the cartridge file and never change after load.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut w = save::Writer::new();
+        w.u8(CART_SAVE_STATE_VERSION);
+        w.bool(self.ram_enabled);
+        w.u8(self.rom_bank_lo);
+        w.u8(self.rom_bank_hi);
+        w.u8(self.ram_bank);
+        w.u8(self.banking_mode);
+        w.bytes(&self.rtc);
+        w.bytes(&self.rtc_latched);
+        w.bool(self.rtc_latch_write.is_some());
+        w.u8(self.rtc_latch_write.unwrap_or(0));
+        w.u32(self.ram.len() as u32);
+        w.bytes(&self.ram);
+        w.into_vec()
+    }
+
+    pub fn load_state(&mut self, data: &[u8]) -> Result<()> {
+        let mut r = save::Reader::new(data);
+        r.expect_version(CART_SAVE_STATE_VERSION, "cartridge")?;
+        self.ram_enabled = r.bool()?;
+        self.rom_bank_lo = r.u8()?;
+        self.rom_bank_hi = r.u8()?;
+        self.ram_bank = r.u8()?;
+        self.banking_mode = r.u8()?;
+        r.bytes_into(&mut self.rtc)?;
+        r.bytes_into(&mut self.rtc_latched)?;
+        let has_latch_write = r.bool()?;
+        let latch_write_val = r.u8()?;
+        self.rtc_latch_write = has_latch_write.then_some(latch_write_val);
+        let ram_len = r.u32()? as usize;
+        self.ram.resize(ram_len, 0);
+        r.bytes_into(&mut self.ram)?;
+        Ok(())
+    }
+}
+
+impl Drop for Cartridge {
+    fn drop(&mut self) {
+        if self.battery {
+            if let Err(e) = self.save_ram() {
+                eprintln!("cart: failed to save battery RAM: {e}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mbc_cart(kind: MbcKind, rom_bank_count: usize) -> Cartridge {
+        let mut cart = Cartridge::blank(0x4000 * rom_bank_count);
+        cart.kind = kind;
+        cart.rom_bank_count = rom_bank_count;
+        cart
+    }
+
+    #[test]
+    fn mbc1_rom_bank_zero_maps_to_one_and_masks_to_5_bits() {
+        let mut cart = mbc_cart(MbcKind::Mbc1, 128);
+        cart.write(0x2000, 0x00); // bank 0 -> treated as 1
+        assert_eq!(cart.rom_bank(), 1);
+
+        cart.write(0x2000, 0x25); // 0x25 & 0x1F == 0x05
+        assert_eq!(cart.rom_bank(), 0x05);
+    }
+
+    #[test]
+    fn mbc1_upper_bits_extend_rom_bank_only_in_rom_banking_mode() {
+        let mut cart = mbc_cart(MbcKind::Mbc1, 128);
+        cart.write(0x2000, 0x01); // low 5 bits
+        cart.write(0x4000, 0x02); // upper 2 bits
+        cart.write(0x6000, 0x00); // ROM banking mode
+
+        assert_eq!(cart.rom_bank(), 0x01 | (0x02 << 5));
+        assert_eq!(cart.ram_bank(), 0);
+
+        cart.write(0x6000, 0x01); // RAM banking mode
+        assert_eq!(cart.rom_bank(), 0x01); // upper bits no longer feed ROM bank
+        assert_eq!(cart.ram_bank(), 0x02); // ...they select the RAM bank instead
+    }
+
+    #[test]
+    fn mbc5_rom_bank_uses_all_9_bits() {
+        let mut cart = mbc_cart(MbcKind::Mbc5, 512);
+        cart.write(0x2000, 0xFF); // low 8 bits
+        cart.write(0x3000, 0x01); // bit 8
+        assert_eq!(cart.rom_bank(), 0x1FF);
+    }
+
+    #[test]
+    fn mbc3_rtc_latches_only_on_zero_then_one_sequence() {
+        let mut cart = mbc_cart(MbcKind::Mbc3, 2);
+        cart.ram_enabled = true;
+        cart.ram_bank = 0x08; // select the seconds register
+        cart.rtc_latched[0] = 0xAA; // sentinel: only `latch_rtc` overwrites this
+
+        // Writing 1 without a preceding 0 must not latch.
+        cart.write(0x6000, 0x01);
+        assert_eq!(cart.read_ram(0xA000), 0xAA);
+
+        // The 0-then-1 sequence does latch, re-snapshotting from the live RTC.
+        cart.write(0x6000, 0x00);
+        cart.write(0x6000, 0x01);
+        assert_ne!(cart.read_ram(0xA000), 0xAA);
+    }
+
+    #[test]
+    fn save_state_round_trips_banking_ram_and_rtc_state() {
+        let mut cart = mbc_cart(MbcKind::Mbc3, 4);
+        cart.ram.resize(0x4000, 0); // two 8 KiB banks, so bank 1 exists
+        cart.write(0x0000, 0x0A); // enable RAM
+        cart.write(0x2000, 0x03); // ROM bank 3
+        cart.write(0x4000, 0x01); // RAM bank 1
+        cart.write_ram(0xA000, 0x42);
+        cart.rtc_latched[1] = 0x11;
+
+        let snapshot = cart.save_state();
+
+        let mut restored = mbc_cart(MbcKind::Mbc3, 4);
+        restored.load_state(&snapshot).expect("a freshly-taken snapshot must load");
+
+        assert!(restored.ram_enabled);
+        assert_eq!(restored.rom_bank_lo, cart.rom_bank_lo);
+        assert_eq!(restored.ram_bank, cart.ram_bank);
+        assert_eq!(restored.read_ram(0xA000), 0x42);
+        assert_eq!(restored.rtc_latched[1], 0x11);
+    }
+
+    #[test]
+    fn ram_disabled_reads_as_0xff_and_ignores_writes() {
+        let mut cart = mbc_cart(MbcKind::Mbc1, 2);
+        cart.ram.resize(0x2000, 0);
+        cart.write_ram(0xA000, 0x42); // RAM not enabled yet
+        assert_eq!(cart.read_ram(0xA000), 0xFF);
+
+        cart.write(0x0000, 0x0A); // enable RAM
+        cart.write_ram(0xA000, 0x42);
+        assert_eq!(cart.read_ram(0xA000), 0x42);
     }
 }
@@ -0,0 +1,400 @@
+// Pluggable memory-mapped I/O devices.
+//
+// Instead of hard-coding every `0xFF00`-range register into `Bus`, each
+// piece of hardware implements `MmioDevice` and `Bus` dispatches reads and
+// writes to the device that owns a given address. New hardware (a link
+// cable device, say) can be added without touching CPU opcode handling.
+use super::bus::JoypadState;
+use super::save;
+use anyhow::Result;
+
+// `read` takes `&self`, not `&mut self`: the disassembler walks code through
+// `Bus::read8` without mutating machine state, and every register here is
+// read-without-side-effects on real hardware anyway (P1's select bits only
+// change on write).
+pub trait MmioDevice {
+    fn read(&self, addr: u16) -> u8;
+    fn write(&mut self, addr: u16, val: u8);
+    fn tick(&mut self, cycles: u8);
+}
+
+/// `0xFF00` - the joypad select/state register.
+pub struct JoypadDevice {
+    select: u8, // bits 4/5 of P1, as last written
+    state: JoypadState,
+    pending_interrupt: bool,
+}
+
+impl JoypadDevice {
+    pub fn new() -> Self {
+        Self { select: 0x00, state: JoypadState::default(), pending_interrupt: false }
+    }
+
+    pub fn set_state(&mut self, state: JoypadState) {
+        let before = self.lines();
+        self.state = state;
+        self.request_on_falling_edge(before);
+    }
+
+    /// Returns and clears whether a selected line went from released to
+    /// pressed since the last call, so `Bus` can request the joypad
+    /// interrupt through `IF`.
+    pub fn take_pending_interrupt(&mut self) -> bool {
+        std::mem::take(&mut self.pending_interrupt)
+    }
+
+    /// The four output lines (bits 0-3 of P1) as currently selected.
+    /// 0 = pressed, matching the register's own polarity.
+    fn lines(&self) -> u8 {
+        self.read(0xFF00) & 0x0F
+    }
+
+    /// The joypad interrupt fires on a 1-to-0 (released-to-pressed)
+    /// transition of any selected line -- not on every state change.
+    fn request_on_falling_edge(&mut self, before: u8) {
+        let after = self.lines();
+        if before & !after & 0x0F != 0 {
+            self.pending_interrupt = true;
+        }
+    }
+
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut w = save::Writer::new();
+        w.u8(self.select);
+        w.into_vec()
+    }
+
+    pub fn load_state(&mut self, data: &[u8]) -> Result<()> {
+        let mut r = save::Reader::new(data);
+        self.select = r.u8()?;
+        Ok(())
+    }
+}
+
+impl MmioDevice for JoypadDevice {
+    fn read(&self, _addr: u16) -> u8 {
+        // Bit = 0 means selected/pressed. Unselected lines return 1.
+        let mut res = 0xCF | (self.select & 0x30);
+        let sel_dpad = self.select & 0x10 == 0;
+        let sel_btn = self.select & 0x20 == 0;
+
+        if sel_dpad {
+            if self.state.right { res &= !0x01; }
+            if self.state.left { res &= !0x02; }
+            if self.state.up { res &= !0x04; }
+            if self.state.down { res &= !0x08; }
+        }
+
+        if sel_btn {
+            if self.state.a { res &= !0x01; }
+            if self.state.b { res &= !0x02; }
+            if self.state.select { res &= !0x04; }
+            if self.state.start { res &= !0x08; }
+        }
+
+        res
+    }
+
+    fn write(&mut self, _addr: u16, val: u8) {
+        let before = self.lines();
+        self.select = val & 0x30;
+        self.request_on_falling_edge(before);
+    }
+
+    fn tick(&mut self, _cycles: u8) {}
+}
+
+/// Internal-clock bit period: the DMG shifts one bit per 512 T-cycles
+/// (8192 Hz), so a full byte takes 8 * 512 = 4096 T-cycles.
+const SERIAL_BIT_PERIOD: u32 = 512;
+
+/// `0xFF01`/`0xFF02` - serial data/control. No link cable is emulated (there's
+/// no peer to shift bits in from), but the transfer still takes real time and
+/// raises the serial interrupt on completion, which is all Blargg-style test
+/// ROMs need: they write a result byte to SB, set `SC = 0x81`, and print
+/// whatever they're shifted out once the interrupt fires.
+pub struct SerialDevice {
+    sb: u8,
+    sc: u8,
+    bits_left: u8,     // 0 when idle, else counts down 8..1 during a transfer
+    cycle_acc: u32,
+    pending_interrupt: bool,
+    output: Vec<u8>, // bytes shifted out, for a host-side capture mode
+}
+
+impl SerialDevice {
+    pub fn new() -> Self {
+        Self {
+            sb: 0,
+            sc: 0,
+            bits_left: 0,
+            cycle_acc: 0,
+            pending_interrupt: false,
+            output: Vec::new(),
+        }
+    }
+
+    /// Returns and clears whether a transfer completed since the last call,
+    /// so `Bus` can request the serial interrupt through `IF`.
+    pub fn take_pending_interrupt(&mut self) -> bool {
+        std::mem::take(&mut self.pending_interrupt)
+    }
+
+    /// Bytes shifted out since the last call, for `--serial-stdout`-style
+    /// capture of test-ROM output.
+    pub fn take_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.output)
+    }
+
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut w = save::Writer::new();
+        w.u8(self.sb);
+        w.u8(self.sc);
+        w.u8(self.bits_left);
+        w.u32(self.cycle_acc);
+        w.into_vec()
+    }
+
+    pub fn load_state(&mut self, data: &[u8]) -> Result<()> {
+        let mut r = save::Reader::new(data);
+        self.sb = r.u8()?;
+        self.sc = r.u8()?;
+        self.bits_left = r.u8()?;
+        self.cycle_acc = r.u32()?;
+        Ok(())
+    }
+}
+
+impl MmioDevice for SerialDevice {
+    fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0xFF01 => self.sb,
+            0xFF02 => self.sc | 0x7E, // unused bits read as 1
+            _ => 0xFF,
+        }
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0xFF01 => self.sb = val,
+            0xFF02 => {
+                self.sc = val;
+                // Bit 7 = transfer start, bit 0 = internal clock. Without an
+                // external clock source there's nothing to shift against, so
+                // only internally-clocked transfers actually run.
+                if val & 0x81 == 0x81 {
+                    self.bits_left = 8;
+                    self.cycle_acc = 0;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn tick(&mut self, cycles: u8) {
+        if self.bits_left == 0 {
+            return;
+        }
+        self.cycle_acc += cycles as u32;
+        while self.cycle_acc >= SERIAL_BIT_PERIOD && self.bits_left > 0 {
+            self.cycle_acc -= SERIAL_BIT_PERIOD;
+            self.bits_left -= 1;
+        }
+        if self.bits_left == 0 {
+            self.output.push(self.sb);
+            self.sc &= !0x80;
+            self.pending_interrupt = true;
+        }
+    }
+}
+
+/// `0xFF04`-`0xFF07` - DIV/TIMA/TMA/TAC.
+pub struct TimerDevice {
+    div: u16,
+    tima: u8,
+    tma: u8,
+    tac: u8,
+    counter: u32,
+    pending_interrupt: bool,
+}
+
+impl TimerDevice {
+    pub fn new() -> Self {
+        Self { div: 0, tima: 0, tma: 0, tac: 0, counter: 0, pending_interrupt: false }
+    }
+
+    fn freq_divider(&self) -> u32 {
+        match self.tac & 0x03 {
+            0 => 1024, // 4096 Hz
+            1 => 16,   // 262144 Hz
+            2 => 64,   // 65536 Hz
+            _ => 256,  // 16384 Hz
+        }
+    }
+
+    /// Returns and clears whether TIMA overflowed since the last call, so
+    /// `Bus` can request the timer interrupt through `IF`.
+    pub fn take_pending_interrupt(&mut self) -> bool {
+        std::mem::take(&mut self.pending_interrupt)
+    }
+
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut w = save::Writer::new();
+        w.u16(self.div);
+        w.u8(self.tima);
+        w.u8(self.tma);
+        w.u8(self.tac);
+        w.u32(self.counter);
+        w.into_vec()
+    }
+
+    pub fn load_state(&mut self, data: &[u8]) -> Result<()> {
+        let mut r = save::Reader::new(data);
+        self.div = r.u16()?;
+        self.tima = r.u8()?;
+        self.tma = r.u8()?;
+        self.tac = r.u8()?;
+        self.counter = r.u32()?;
+        Ok(())
+    }
+}
+
+impl MmioDevice for TimerDevice {
+    fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0xFF04 => (self.div >> 8) as u8,
+            0xFF05 => self.tima,
+            0xFF06 => self.tma,
+            0xFF07 => self.tac | 0xF8,
+            _ => 0xFF,
+        }
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0xFF04 => self.div = 0, // any write resets the whole counter
+            0xFF05 => self.tima = val,
+            0xFF06 => self.tma = val,
+            0xFF07 => self.tac = val & 0x07,
+            _ => {}
+        }
+    }
+
+    fn tick(&mut self, cycles: u8) {
+        // `cycles` is already T-cycles (see `Cpu::mem_read8`), so DIV just
+        // advances by that count directly -- its upper 8 bits then change
+        // every 256 T-cycles, i.e. at 16384 Hz.
+        self.div = self.div.wrapping_add(cycles as u16);
+
+        if self.tac & 0x04 == 0 {
+            return;
+        }
+
+        self.counter += cycles as u32;
+        let period = self.freq_divider();
+        while self.counter >= period {
+            self.counter -= period;
+            let (new, overflow) = self.tima.overflowing_add(1);
+            if overflow {
+                self.tima = self.tma;
+                self.pending_interrupt = true;
+            } else {
+                self.tima = new;
+            }
+        }
+    }
+}
+
+/// `0xFF0F` - the IF register. Kept as its own device so any source (PPU,
+/// timer, serial, joypad) requests bits the same way instead of `Bus`
+/// reaching into a bare field.
+pub struct InterruptFlagDevice {
+    flags: u8,
+}
+
+impl InterruptFlagDevice {
+    pub fn new() -> Self {
+        Self { flags: 0 }
+    }
+
+    pub fn get(&self) -> u8 {
+        self.flags
+    }
+
+    pub fn request(&mut self, bit: u8) {
+        self.flags |= 1 << bit;
+    }
+
+    pub fn clear(&mut self, bit: u8) {
+        self.flags &= !(1 << bit);
+    }
+
+    pub fn set(&mut self, val: u8) {
+        self.flags = val;
+    }
+
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut w = save::Writer::new();
+        w.u8(self.flags);
+        w.into_vec()
+    }
+
+    pub fn load_state(&mut self, data: &[u8]) -> Result<()> {
+        let mut r = save::Reader::new(data);
+        self.flags = r.u8()?;
+        Ok(())
+    }
+}
+
+impl MmioDevice for InterruptFlagDevice {
+    fn read(&self, _addr: u16) -> u8 {
+        self.flags
+    }
+
+    fn write(&mut self, _addr: u16, val: u8) {
+        self.flags = val;
+    }
+
+    fn tick(&mut self, _cycles: u8) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serial_transfer_takes_4096_t_cycles_then_interrupts_and_captures_output() {
+        let mut dev = SerialDevice::new();
+        dev.write(0xFF01, 0x42); // SB
+        dev.write(0xFF02, 0x81); // start an internally-clocked transfer
+
+        // 8 bits * 512 T-cycles/bit == 4096 T-cycles for the whole byte.
+        dev.tick(255);
+        dev.tick(255);
+        assert!(!dev.take_pending_interrupt());
+        assert!(dev.take_output().is_empty());
+
+        // Cross the remaining distance to exactly 4096 (510 so far).
+        for _ in 0..14 {
+            dev.tick(255); // 510 + 14*255 == 4080
+        }
+        dev.tick(16); // 4080 + 16 == 4096
+
+        assert!(dev.take_pending_interrupt());
+        assert_eq!(dev.take_output(), vec![0x42]);
+        assert_eq!(dev.read(0xFF02) & 0x80, 0); // SC's start bit clears on completion
+    }
+
+    #[test]
+    fn timer_tima_overflow_reloads_tma_and_requests_interrupt() {
+        let mut dev = TimerDevice::new();
+        dev.write(0xFF06, 0x10); // TMA
+        dev.write(0xFF05, 0xFF); // TIMA one tick from overflowing
+        dev.write(0xFF07, 0x05); // timer enabled, divider 16 (262144 Hz)
+
+        dev.tick(16); // exactly one divider period -> TIMA 0xFF -> 0x00, overflow
+
+        assert!(dev.take_pending_interrupt());
+        assert_eq!(dev.read(0xFF05), 0x10); // reloaded from TMA
+    }
+}
@@ -1,11 +1,18 @@
-// emulator module rootmod bus;
+// emulator module root
+mod apu;
+mod bus;
 mod cart;
 mod cpu;
+mod decode;
+mod device;
+mod opcodes;
 mod ppu;
+mod save;
 
 use anyhow::Result;
 
 pub use bus::JoypadState;
+pub use cpu::CpuError;
 
 pub struct Emulator {
     cpu: cpu::Cpu,
@@ -21,14 +28,19 @@ impl Emulator {
         Ok(Self { cpu, bus })
     }
 
-    pub fn run_frame(&mut self) {
-        // DMG: ~70224 cycles per frame (approx)
+    /// Run roughly one frame's worth of cycles. Stops early and surfaces
+    /// the error if the CPU hits an illegal or unimplemented opcode, so a
+    /// front-end can report exactly where the ROM diverged instead of the
+    /// whole process aborting.
+    pub fn run_frame(&mut self) -> Result<(), CpuError> {
+        // DMG: ~70224 cycles per frame (approx). `Cpu::step` ticks the bus
+        // itself (per bus access, M-cycle granularity) as it executes, so
+        // there's no separate bus-stepping pass here.
         let mut cycles = 0u32;
         while cycles < 70224 {
-            let c = self.cpu.step(&mut self.bus);
-            self.bus.step(c);
-            cycles += c as u32;
+            cycles += self.cpu.step(&mut self.bus)? as u32;
         }
+        Ok(())
     }
 
     pub fn framebuffer_rgba(&self) -> &[u8] {
@@ -36,6 +48,67 @@ impl Emulator {
     }
 
     pub fn set_joypad(&mut self, s: JoypadState) {
-        self.bus.joypad = s;
+        self.bus.set_joypad(s);
+    }
+
+    /// Take every audio sample (interleaved stereo f32, 48 kHz) generated
+    /// since the last call, for a front-end to feed to its output stream.
+    pub fn drain_audio_samples(&mut self) -> Vec<f32> {
+        self.bus.drain_audio_samples()
+    }
+
+    /// Take every byte shifted out over the serial port since the last
+    /// call, for a front-end running in test-ROM capture mode.
+    pub fn drain_serial_output(&mut self) -> Vec<u8> {
+        self.bus.drain_serial_output()
+    }
+
+    /// Flush battery-backed cartridge RAM to its `.sav` file. No-op for
+    /// carts without a battery. A front-end should call this on exit (and
+    /// may call it periodically) rather than relying on `Cartridge`'s
+    /// `Drop` impl, since a `winit` event loop never returns to drop `self`.
+    pub fn save_battery_ram(&self) -> Result<()> {
+        self.bus.cart.save_ram()
+    }
+
+    /// Serialize the whole machine (CPU + bus, including VRAM/WRAM/OAM and
+    /// the PPU) into a versioned snapshot that `load_state` can restore.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut w = save::Writer::new();
+        w.bytes(SAVE_STATE_MAGIC);
+        w.u8(SAVE_STATE_VERSION);
+
+        let cpu_state = self.cpu.save_state();
+        w.u32(cpu_state.len() as u32);
+        w.bytes(&cpu_state);
+
+        let bus_state = self.bus.save_state();
+        w.u32(bus_state.len() as u32);
+        w.bytes(&bus_state);
+
+        w.into_vec()
+    }
+
+    /// Restore a snapshot produced by `save_state`. Rejects anything that
+    /// isn't our magic/version so stale or foreign snapshots fail cleanly
+    /// instead of corrupting machine state.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<()> {
+        let mut r = save::Reader::new(data);
+        let magic = r.bytes(SAVE_STATE_MAGIC.len())?;
+        if magic != SAVE_STATE_MAGIC {
+            anyhow::bail!("not a Game Boy save state (bad magic)");
+        }
+        r.expect_version(SAVE_STATE_VERSION, "emulator")?;
+
+        let cpu_len = r.u32()? as usize;
+        self.cpu.load_state(r.bytes(cpu_len)?)?;
+
+        let bus_len = r.u32()? as usize;
+        self.bus.load_state(r.bytes(bus_len)?)?;
+
+        Ok(())
     }
 }
+
+const SAVE_STATE_MAGIC: &[u8] = b"GBRS";
+const SAVE_STATE_VERSION: u8 = 1;
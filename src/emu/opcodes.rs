@@ -0,0 +1,18 @@
+// Per-opcode timing consumed by the non-mutating disassembler (`decode.rs`)
+// so it can report accurate cycle counts without executing anything. Holds
+// the *base* cost for every opcode -- for JR/JP/CALL/RET conditionals
+// that's the not-taken cost, matching how `decode.rs` reports them.
+//
+// NOTE: `Cpu::step`'s own cycle accounting does NOT read these tables --
+// it still hard-codes its own literal cycle count per match arm (and adds
+// the extra cycles itself when a conditional branch is taken). The two are
+// meant to agree and are cross-checked by hand against `instructions.in`,
+// but nothing enforces that at compile time; wiring `Cpu::step` to this
+// table instead is a real refactor of every opcode handler, deliberately
+// not attempted here.
+//
+// The tables themselves are generated by `build.rs` from the declarative
+// rules in `instructions.in` at the crate root, instead of being derived
+// here by hand -- that's what used to make individual cycle counts easy to
+// get subtly wrong in a sprawling `match`.
+include!(concat!(env!("OUT_DIR"), "/opcode_cycles_generated.rs"));
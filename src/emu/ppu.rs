@@ -1,5 +1,9 @@
+use super::save;
+use anyhow::Result;
+
 const SCREEN_W: usize = 160;
 const SCREEN_H: usize = 144;
+const PPU_SAVE_STATE_VERSION: u8 = 3; // v3 adds the window line counter
 
 pub struct Ppu {
     pub fb: Vec<u8>, // RGBA 160*144*4
@@ -10,10 +14,17 @@ pub struct Ppu {
     pub ly: u8,
     pub lyc: u8,
     pub bgp: u8,
+    pub obp0: u8,
+    pub obp1: u8,
     pub wy: u8,
     pub wx: u8,
-    cycle_acc: u32,
+    line_cycle: u32, // dot position within the current 456-dot scanline
     mode: u8,
+    win_line: u8, // window's own internal line counter; only advances on lines it's actually drawn
+    // BG/window color ID (0-3, pre-palette) of the last rendered frame, one
+    // per pixel -- sprites need this to honor the BG-priority attribute bit,
+    // which hides a sprite behind any non-zero BG/window color.
+    bg_color_ids: Vec<u8>,
 }
 
 impl Ppu {
@@ -27,74 +38,163 @@ impl Ppu {
             ly: 0,
             lyc: 0,
             bgp: 0xFC, // default: 00->white, 11->black
+            obp0: 0xFF,
+            obp1: 0xFF,
             wy: 0,
             wx: 0,
-            cycle_acc: 0,
+            line_cycle: 0,
             mode: 1,
+            win_line: 0,
+            bg_color_ids: vec![0; SCREEN_W * SCREEN_H],
         }
     }
 
-    pub fn step(&mut self, cycles: u32, vram: &[u8]) -> (bool, bool) {
+    // Dot budget within a 456-dot scanline: Mode 2 (OAM scan) is fixed at 80
+    // dots; Mode 3 (pixel transfer) is a fixed 172 here (real hardware varies
+    // it with sprite/window fetch penalties, which this PPU doesn't model
+    // yet); Mode 0 (HBlank) fills the remainder.
+    const MODE2_DOTS: u32 = 80;
+    const MODE3_END_DOTS: u32 = 252;
+
+    fn mode_for(&self, line_cycle: u32) -> u8 {
+        if self.ly >= 144 {
+            1
+        } else if line_cycle < Self::MODE2_DOTS {
+            2
+        } else if line_cycle < Self::MODE3_END_DOTS {
+            3
+        } else {
+            0
+        }
+    }
+
+    /// Advance the PPU dot-by-dot (in `cycles`-sized chunks) so mode
+    /// transitions, LY changes, and STAT interrupts happen at the right
+    /// point in the scanline instead of all at once when LY wraps. Each
+    /// scanline is rendered exactly once, right as Mode 3 begins, using
+    /// whatever SCX/SCY/BGP/LCDC/etc are live at that moment -- that's what
+    /// lets mid-frame raster effects show up correctly.
+    pub fn step(&mut self, cycles: u32, vram: &[u8], oam: &[u8]) -> (bool, bool) {
         if self.lcdc & 0x80 == 0 {
             self.ly = 0;
+            self.line_cycle = 0;
+            self.win_line = 0;
             self.fb.fill(0xFF);
             self.mode = 0;
-            self.update_stat(false);
+            self.update_stat(false, true);
             return (false, false);
         }
 
-        self.cycle_acc += cycles;
+        let mut remaining = cycles;
         let mut vblank = false;
         let mut stat_irq = false;
 
-        while self.cycle_acc >= 456 {
-            self.cycle_acc -= 456;
-            self.ly = self.ly.wrapping_add(1);
+        while remaining > 0 {
+            let boundary = if self.ly >= 144 {
+                456
+            } else if self.line_cycle < Self::MODE2_DOTS {
+                Self::MODE2_DOTS
+            } else if self.line_cycle < Self::MODE3_END_DOTS {
+                Self::MODE3_END_DOTS
+            } else {
+                456
+            };
+            let step = remaining.min(boundary - self.line_cycle);
+            self.line_cycle += step;
+            remaining -= step;
 
-            if self.ly == 144 {
-                vblank = true;
-            } else if self.ly >= 154 {
-                self.ly = 0;
-                self.render_background(vram);
+            let mut line_changed = false;
+            let mut entered_vblank = false;
+            if self.line_cycle >= 456 {
+                self.line_cycle -= 456;
+                self.ly = self.ly.wrapping_add(1);
+                line_changed = true;
+                if self.ly == 144 {
+                    entered_vblank = true;
+                    vblank = true;
+                } else if self.ly >= 154 {
+                    self.ly = 0;
+                    self.win_line = 0;
+                }
             }
-        }
 
-        let mode = if self.ly >= 144 {
-            1
-        } else if self.cycle_acc < 80 {
-            2
-        } else if self.cycle_acc < 252 {
-            3
-        } else {
-            0
-        };
-        let mode_changed = mode != self.mode;
-        self.mode = mode;
+            let new_mode = self.mode_for(self.line_cycle);
+            let mode_changed = new_mode != self.mode;
+            self.mode = new_mode;
 
-        if self.update_stat(vblank || mode_changed) {
-            stat_irq = true;
+            if mode_changed && new_mode == 3 {
+                self.render_scanline(vram, oam);
+            }
+
+            if line_changed || mode_changed {
+                if self.update_stat(entered_vblank, mode_changed) {
+                    stat_irq = true;
+                }
+            }
         }
 
         (vblank, stat_irq)
     }
 
-    fn render_background(&mut self, vram: &[u8]) {
-        if self.lcdc & 0x80 == 0 {
-            self.fb.fill(0xFF);
+    /// Render just `self.ly` into `fb`, using the live register values (this
+    /// is what lets mid-scanline SCX/SCY/BGP/LCDC writes show up correctly
+    /// instead of the whole frame being drawn from whatever the registers
+    /// happened to be when LY wrapped).
+    fn render_scanline(&mut self, vram: &[u8], oam: &[u8]) {
+        let y = self.ly as usize;
+        if y >= SCREEN_H {
             return;
         }
 
         let bg_tile_map_base = if self.lcdc & 0x08 != 0 { 0x9C00 } else { 0x9800 };
         let use_signed = self.lcdc & 0x10 == 0;
+        let sy = y.wrapping_add(self.scy as usize) & 0xFF;
+
+        for x in 0..SCREEN_W {
+            let sx = x.wrapping_add(self.scx as usize) & 0xFF;
+            let tile_x = sx / 8;
+            let tile_y = sy / 8;
+            let tile_map_index = tile_y * 32 + tile_x;
+            let tile_num = vram[(bg_tile_map_base - 0x8000 + tile_map_index) as usize];
+            let tile_addr = if use_signed {
+                let base = 0x9000i32 + (tile_num as i8 as i32) * 16;
+                base as u16
+            } else {
+                0x8000u16 + (tile_num as u16) * 16
+            };
+
+            let line = (sy % 8) as u16;
+            let byte0 = vram[(tile_addr + line * 2 - 0x8000) as usize];
+            let byte1 = vram[(tile_addr + line * 2 + 1 - 0x8000) as usize];
+            let bit = 7 - (sx % 8);
+            let color_id = ((byte1 >> bit) & 1) << 1 | ((byte0 >> bit) & 1);
+            let shade = Self::map_palette(self.bgp, color_id);
 
-        for y in 0..SCREEN_H {
-            let sy = y.wrapping_add(self.scy as usize) & 0xFF;
+            let idx = (y * SCREEN_W + x) * 4;
+            self.fb[idx] = shade;
+            self.fb[idx + 1] = shade;
+            self.fb[idx + 2] = shade;
+            self.fb[idx + 3] = 0xFF;
+            self.bg_color_ids[y * SCREEN_W + x] = color_id;
+        }
+
+        // Window overlay. The window's own line counter only advances on
+        // lines where it's actually visible, so scrolling WY mid-frame
+        // doesn't skip rows of window content.
+        let win_x = self.wx.wrapping_sub(7); // WX is offset by 7
+        let window_visible =
+            self.lcdc & 0x20 != 0 && y >= self.wy as usize && (win_x as usize) < SCREEN_W;
+        if window_visible {
+            let win_tile_map_base = if self.lcdc & 0x40 != 0 { 0x9C00 } else { 0x9800 };
+            let tile_y = (self.win_line as usize) / 8;
             for x in 0..SCREEN_W {
-                let sx = x.wrapping_add(self.scx as usize) & 0xFF;
-                let tile_x = sx / 8;
-                let tile_y = sy / 8;
+                if x < win_x as usize {
+                    continue;
+                }
+                let wx = x - win_x as usize;
+                let tile_x = wx / 8;
                 let tile_map_index = tile_y * 32 + tile_x;
-                let tile_num = vram[(bg_tile_map_base - 0x8000 + tile_map_index) as usize];
+                let tile_num = vram[(win_tile_map_base - 0x8000 + tile_map_index) as usize];
                 let tile_addr = if use_signed {
                     let base = 0x9000i32 + (tile_num as i8 as i32) * 16;
                     base as u16
@@ -102,10 +202,10 @@ impl Ppu {
                     0x8000u16 + (tile_num as u16) * 16
                 };
 
-                let line = (sy % 8) as u16;
+                let line = (self.win_line as u16) % 8;
                 let byte0 = vram[(tile_addr + line * 2 - 0x8000) as usize];
                 let byte1 = vram[(tile_addr + line * 2 + 1 - 0x8000) as usize];
-                let bit = 7 - (sx % 8);
+                let bit = 7 - (wx % 8);
                 let color_id = ((byte1 >> bit) & 1) << 1 | ((byte0 >> bit) & 1);
                 let shade = Self::map_palette(self.bgp, color_id);
 
@@ -114,52 +214,93 @@ impl Ppu {
                 self.fb[idx + 1] = shade;
                 self.fb[idx + 2] = shade;
                 self.fb[idx + 3] = 0xFF;
+                self.bg_color_ids[y * SCREEN_W + x] = color_id;
             }
+            self.win_line = self.win_line.wrapping_add(1);
         }
 
-        // Window overlay (no sprites yet)
-        if self.lcdc & 0x20 != 0 {
-            let win_tile_map_base = if self.lcdc & 0x40 != 0 { 0x9C00 } else { 0x9800 };
-            let win_x = self.wx.wrapping_sub(7); // WX is offset by 7
-            let win_y = self.wy;
-            for y in 0..SCREEN_H {
-                if y < win_y as usize {
+        self.render_sprites_on_line(y, vram, oam);
+    }
+
+    /// Composite OAM sprites over the BG/window pixels `render_scanline`
+    /// just drew for line `y`. DMG (non-CGB) priority: among sprites
+    /// covering a pixel, lower X wins, ties broken by lower OAM index; at
+    /// most 10 sprites per scanline, in OAM order.
+    fn render_sprites_on_line(&mut self, y: usize, vram: &[u8], oam: &[u8]) {
+        if self.lcdc & 0x02 == 0 {
+            return;
+        }
+        let tall = self.lcdc & 0x04 != 0;
+        let sprite_h: i32 = if tall { 16 } else { 8 };
+        let ly = y as i32;
+
+        // Scan all 40 OAM entries, keep the first 10 (in OAM order) whose Y
+        // range covers this scanline.
+        let mut on_line: Vec<usize> = Vec::with_capacity(10);
+        for i in 0..40 {
+            let entry = &oam[i * 4..i * 4 + 4];
+            let sy = entry[0] as i32 - 16;
+            if ly >= sy && ly < sy + sprite_h {
+                on_line.push(i);
+                if on_line.len() == 10 {
+                    break;
+                }
+            }
+        }
+
+        // Lowest priority first so the final composite pass draws the
+        // highest-priority sprite (lowest X, lowest OAM index) last.
+        on_line.sort_by(|&a, &b| {
+            let xa = oam[a * 4 + 1];
+            let xb = oam[b * 4 + 1];
+            xb.cmp(&xa).then(b.cmp(&a))
+        });
+
+        for i in on_line {
+            let entry = &oam[i * 4..i * 4 + 4];
+            let sy = entry[0] as i32 - 16;
+            let x = entry[1] as i32 - 8;
+            let mut tile = entry[2];
+            let attrs = entry[3];
+            let palette = if attrs & 0x10 != 0 { self.obp1 } else { self.obp0 };
+            let x_flip = attrs & 0x20 != 0;
+            let y_flip = attrs & 0x40 != 0;
+            let behind_bg = attrs & 0x80 != 0;
+
+            if tall {
+                tile &= 0xFE; // 8x16 mode ignores bit 0 of the tile index
+            }
+
+            let mut row = ly - sy;
+            if y_flip {
+                row = sprite_h - 1 - row;
+            }
+            let tile_addr = 0x8000u16 + (tile as u16) * 16 + (row as u16) * 2;
+            let byte0 = vram[(tile_addr - 0x8000) as usize];
+            let byte1 = vram[(tile_addr + 1 - 0x8000) as usize];
+
+            for col in 0..8i32 {
+                let px = x + col;
+                if px < 0 || px >= SCREEN_W as i32 {
                     continue;
                 }
-                let wy = y - win_y as usize;
-                let tile_y = wy / 8;
-                for x in 0..SCREEN_W {
-                    if x < win_x as usize {
-                        continue;
-                    }
-                    let wx = x - win_x as usize;
-                    let tile_x = wx / 8;
-                    let tile_map_index = tile_y * 32 + tile_x;
-                    let tile_num = vram[(win_tile_map_base - 0x8000 + tile_map_index) as usize];
-                    let tile_addr = if use_signed {
-                        let base = 0x9000i32 + (tile_num as i8 as i32) * 16;
-                        base as u16
-                    } else {
-                        0x8000u16 + (tile_num as u16) * 16
-                    };
-
-                    let line = (wy % 8) as u16;
-                    let byte0 = vram[(tile_addr + line * 2 - 0x8000) as usize];
-                    let byte1 = vram[(tile_addr + line * 2 + 1 - 0x8000) as usize];
-                    let bit = 7 - (wx % 8);
-                    let color_id = ((byte1 >> bit) & 1) << 1 | ((byte0 >> bit) & 1);
-                    let shade = Self::map_palette(self.bgp, color_id);
-
-                    let idx = (y * SCREEN_W + x) * 4;
-                    self.fb[idx] = shade;
-                    self.fb[idx + 1] = shade;
-                    self.fb[idx + 2] = shade;
-                    self.fb[idx + 3] = 0xFF;
+                let bit = if x_flip { col } else { 7 - col };
+                let color_id = ((byte1 >> bit) & 1) << 1 | ((byte0 >> bit) & 1);
+                if color_id == 0 {
+                    continue; // transparent
+                }
+                if behind_bg && self.bg_color_ids[y * SCREEN_W + px as usize] != 0 {
+                    continue; // hidden behind non-zero BG/window color
                 }
+
+                let shade = Self::map_palette(palette, color_id);
+                let idx = (y * SCREEN_W + px as usize) * 4;
+                self.fb[idx] = shade;
+                self.fb[idx + 1] = shade;
+                self.fb[idx + 2] = shade;
+                self.fb[idx + 3] = 0xFF;
             }
         }
-
-        self.mode = 0; // HBlank
     }
 
     fn map_palette(palette: u8, color_id: u8) -> u8 {
@@ -176,7 +317,54 @@ impl Ppu {
         &self.fb
     }
 
-    fn update_stat(&mut self, vblank: bool) -> bool {
+    /// Serialize the PPU's registers and render-timing state. The
+    /// framebuffer itself isn't included -- it's redrawn line by line from
+    /// VRAM and these registers as the PPU steps through the next frame.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut w = save::Writer::new();
+        w.u8(PPU_SAVE_STATE_VERSION);
+        w.u8(self.lcdc);
+        w.u8(self.stat);
+        w.u8(self.scy);
+        w.u8(self.scx);
+        w.u8(self.ly);
+        w.u8(self.lyc);
+        w.u8(self.bgp);
+        w.u8(self.obp0);
+        w.u8(self.obp1);
+        w.u8(self.wy);
+        w.u8(self.wx);
+        w.u32(self.line_cycle);
+        w.u8(self.mode);
+        w.u8(self.win_line);
+        w.into_vec()
+    }
+
+    pub fn load_state(&mut self, data: &[u8]) -> Result<()> {
+        let mut r = save::Reader::new(data);
+        r.expect_version(PPU_SAVE_STATE_VERSION, "PPU")?;
+        self.lcdc = r.u8()?;
+        self.stat = r.u8()?;
+        self.scy = r.u8()?;
+        self.scx = r.u8()?;
+        self.ly = r.u8()?;
+        self.lyc = r.u8()?;
+        self.bgp = r.u8()?;
+        self.obp0 = r.u8()?;
+        self.obp1 = r.u8()?;
+        self.wy = r.u8()?;
+        self.wx = r.u8()?;
+        self.line_cycle = r.u32()?;
+        self.mode = r.u8()?;
+        self.win_line = r.u8()?;
+        Ok(())
+    }
+
+    /// Update the LYC-match flag and (only on an actual mode change) fire
+    /// the matching STAT interrupt condition. `mode_changed` gates the mode
+    /// interrupt bits so a mode that's merely still active doesn't keep
+    /// re-requesting the interrupt every time this is called.
+    fn update_stat(&mut self, entered_vblank: bool, mode_changed: bool) -> bool {
         let mut irq = false;
         let lyc_match = self.ly == self.lyc;
         if lyc_match {
@@ -188,24 +376,101 @@ impl Ppu {
         // STAT mode bits
         self.stat = (self.stat & !0x03) | (self.mode & 0x03);
 
-        // Interrupt conditions: bit6 (LYC), bit5 (OAM), bit4 (VBlank), bit3 (HBlank)
+        // Interrupt conditions: bit6 (LYC), bit5 (OAM), bit4 (VBlank), bit3 (HBlank).
+        // LYC can fire any time LY changes; the mode conditions only fire
+        // right as that mode is entered.
         if lyc_match && (self.stat & 0x40 != 0) {
             irq = true;
         }
-        if self.mode == 2 && (self.stat & 0x20 != 0) {
-            irq = true;
-        }
-        if self.mode == 1 && (self.stat & 0x10 != 0) {
-            irq = true;
-        }
-        if self.mode == 0 && (self.stat & 0x08 != 0) {
-            irq = true;
+        if mode_changed {
+            if self.mode == 2 && (self.stat & 0x20 != 0) {
+                irq = true;
+            }
+            if self.mode == 1 && (self.stat & 0x10 != 0) {
+                irq = true;
+            }
+            if self.mode == 0 && (self.stat & 0x08 != 0) {
+                irq = true;
+            }
         }
 
-        if vblank {
+        if entered_vblank {
             self.stat |= 0x01;
         }
 
         irq
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sprite_behind_bg_flag_hides_sprite_under_nonzero_bg_color() {
+        let mut ppu = Ppu::new();
+        ppu.lcdc = 0x02; // sprites enabled, nothing else needed for this check
+        ppu.obp0 = 0x00; // color 1 -> shade 0xFF, so a drawn sprite would change fb[0]
+        ppu.bg_color_ids[0] = 1; // pretend the BG already drew a non-zero color here
+
+        let mut vram = vec![0u8; 0x2000];
+        vram[0] = 0xFF; // tile 0 byte0: every pixel's low bit set -> color_id 1
+
+        let mut oam = vec![0u8; 40 * 4];
+        oam[0] = 16; // Y: sy = 16 - 16 = 0, covers line 0
+        oam[1] = 8; // X: x = 8 - 8 = 0
+        oam[2] = 0; // tile 0
+        oam[3] = 0x80; // behind_bg
+
+        ppu.render_sprites_on_line(0, &vram, &oam);
+
+        assert_eq!(ppu.fb[0], 0); // untouched -- still the framebuffer's initial value
+    }
+
+    #[test]
+    fn sprite_priority_ties_break_toward_lower_oam_index() {
+        let mut ppu = Ppu::new();
+        ppu.lcdc = 0x02;
+        ppu.obp0 = 0x00; // color 1 -> shade 0xFF
+        ppu.obp1 = 0xFF; // color 2 -> shade 0x00
+
+        let mut vram = vec![0u8; 0x2000];
+        vram[0] = 0xFF; // tile 0: color_id 1 everywhere
+        vram[17] = 0xFF; // tile 1 byte1: color_id 2 everywhere
+
+        let mut oam = vec![0u8; 40 * 4];
+        // OAM index 0: tile 0 (color 1, obp0), at x=0.
+        oam[0] = 16;
+        oam[1] = 8;
+        oam[2] = 0;
+        oam[3] = 0x00;
+        // OAM index 5: same X, tile 1 (color 2, obp1) -- a higher OAM index
+        // at the same X must lose to index 0.
+        oam[5 * 4] = 16;
+        oam[5 * 4 + 1] = 8;
+        oam[5 * 4 + 2] = 1;
+        oam[5 * 4 + 3] = 0x10;
+
+        ppu.render_sprites_on_line(0, &vram, &oam);
+
+        assert_eq!(ppu.fb[0], 0xFF); // OAM index 0's color won the tie
+    }
+
+    #[test]
+    fn scanline_dot_boundaries_drive_mode_and_ly() {
+        let mut ppu = Ppu::new();
+        ppu.lcdc = 0x80; // display on, nothing else drawn
+        let vram = vec![0u8; 0x2000];
+        let oam = vec![0u8; 40 * 4];
+
+        ppu.step(Ppu::MODE2_DOTS, &vram, &oam); // 0..80: mode 2 (OAM scan)
+        assert_eq!(ppu.mode, 3);
+
+        ppu.step(Ppu::MODE3_END_DOTS - Ppu::MODE2_DOTS, &vram, &oam); // 80..252: mode 3
+        assert_eq!(ppu.mode, 0);
+
+        ppu.step(456 - Ppu::MODE3_END_DOTS, &vram, &oam); // 252..456: mode 0, then wraps
+        assert_eq!(ppu.ly, 1);
+        assert_eq!(ppu.mode, 2);
+    }
+}
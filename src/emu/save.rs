@@ -0,0 +1,103 @@
+// Tiny binary cursor shared by every `save_state`/`load_state` pair in the
+// emulator, so the CPU, PPU, and bus snapshots compose into one machine
+// save state without each hand-rolling offset math.
+use anyhow::{bail, Result};
+
+pub struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    pub fn new() -> Self {
+        Self { buf: Vec::new() }
+    }
+
+    pub fn u8(&mut self, v: u8) {
+        self.buf.push(v);
+    }
+
+    pub fn bool(&mut self, v: bool) {
+        self.buf.push(v as u8);
+    }
+
+    pub fn u16(&mut self, v: u16) {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, v: u32) {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+    }
+
+    pub fn bytes(&mut self, v: &[u8]) {
+        self.buf.extend_from_slice(v);
+    }
+
+    pub fn into_vec(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+pub struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    pub fn u8(&mut self) -> Result<u8> {
+        let v = *self
+            .data
+            .get(self.pos)
+            .ok_or_else(|| anyhow::anyhow!("truncated save state"))?;
+        self.pos += 1;
+        Ok(v)
+    }
+
+    pub fn bool(&mut self) -> Result<bool> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub fn u16(&mut self) -> Result<u16> {
+        let lo = self.u8()? as u16;
+        let hi = self.u8()? as u16;
+        Ok((hi << 8) | lo)
+    }
+
+    pub fn u32(&mut self) -> Result<u32> {
+        let lo = self.u16()? as u32;
+        let hi = self.u16()? as u32;
+        Ok((hi << 16) | lo)
+    }
+
+    pub fn bytes(&mut self, n: usize) -> Result<&'a [u8]> {
+        let end = self.pos + n;
+        let slice = self
+            .data
+            .get(self.pos..end)
+            .ok_or_else(|| anyhow::anyhow!("truncated save state"))?;
+        self.pos = end;
+        Ok(slice)
+    }
+
+    pub fn bytes_into(&mut self, out: &mut [u8]) -> Result<()> {
+        out.copy_from_slice(self.bytes(out.len())?);
+        Ok(())
+    }
+
+    /// Everything not yet consumed -- used to hand off to a nested
+    /// `load_state` whose own length was written without a prefix.
+    pub fn rest(&self) -> &'a [u8] {
+        &self.data[self.pos..]
+    }
+
+    pub fn expect_version(&mut self, expected: u8, what: &str) -> Result<()> {
+        let got = self.u8()?;
+        if got != expected {
+            bail!("unsupported {what} save-state version {got} (expected {expected})");
+        }
+        Ok(())
+    }
+}
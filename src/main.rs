@@ -1,6 +1,9 @@
 use anyhow::Result;
 use clap::Parser;
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
 use pixels::{Pixels, SurfaceTexture};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
 use winit::{
     dpi::LogicalSize,
     event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent},
@@ -10,6 +13,42 @@ use winit::{
 
 mod emu;
 
+/// Shared between the emulator (producer, filling after every frame) and the
+/// `cpal` output callback (consumer, draining at the host's own pace).
+type AudioQueue = Arc<Mutex<VecDeque<f32>>>;
+
+/// Open the default output device and start a stream that pulls interleaved
+/// stereo f32 samples from `queue`, mirroring how `pixels`/`winit` own the
+/// video side -- this owns the host audio side. Returns the `Stream` so the
+/// caller can keep it alive for the process lifetime (dropping it stops
+/// playback).
+fn start_audio_output(queue: AudioQueue) -> Result<cpal::Stream> {
+    let host = cpal::default_host();
+    let device = host
+        .default_output_device()
+        .ok_or_else(|| anyhow::anyhow!("no audio output device available"))?;
+    let config = device.default_output_config()?;
+    let channels = config.channels() as usize;
+
+    let stream = device.build_output_stream(
+        &config.into(),
+        move |data: &mut [f32], _| {
+            let mut queue = queue.lock().unwrap();
+            for frame in data.chunks_mut(channels) {
+                let left = queue.pop_front().unwrap_or(0.0);
+                let right = queue.pop_front().unwrap_or(left);
+                for (i, sample) in frame.iter_mut().enumerate() {
+                    *sample = if i % 2 == 0 { left } else { right };
+                }
+            }
+        },
+        |e| eprintln!("audio output error: {e}"),
+        None,
+    )?;
+    stream.play()?;
+    Ok(stream)
+}
+
 #[derive(Parser, Debug)]
 struct Args {
     /// Path to .gb ROM file (use your own/homebrew ROM)
@@ -18,6 +57,11 @@ struct Args {
     /// Scale factor for the 160x144 screen
     #[arg(long, default_value_t = 4)]
     scale: u32,
+
+    /// Stream serial port output to stdout as it's shifted out, for
+    /// automated test-ROM runs (Blargg-style ROMs print results over SB/SC).
+    #[arg(long)]
+    serial_stdout: bool,
 }
 
 fn main() -> Result<()> {
@@ -48,13 +92,35 @@ fn main() -> Result<()> {
 
     // Simple input state (Game Boy buttons)
     let mut input = emu::JoypadState::default();
+    let mut frames_since_battery_save = 0u32;
+
+    let audio_queue: AudioQueue = Arc::new(Mutex::new(VecDeque::new()));
+    // Keep the stream alive for the rest of `main`; dropping it would stop
+    // playback. If no output device is available (e.g. headless CI), just
+    // run silently rather than failing the whole emulator.
+    let _audio_stream = match start_audio_output(audio_queue.clone()) {
+        Ok(stream) => Some(stream),
+        Err(e) => {
+            eprintln!("audio: {e}, continuing without sound");
+            None
+        }
+    };
 
     event_loop.run(move |event, _, control_flow| {
         *control_flow = ControlFlow::Poll;
 
         match event {
             Event::WindowEvent { event, .. } => match event {
-                WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
+                WindowEvent::CloseRequested => {
+                    // `event_loop.run` never returns, so `emu` (and the
+                    // `Cartridge` inside it) is never dropped on exit --
+                    // flush battery RAM explicitly instead of relying on
+                    // `Drop`.
+                    if let Err(e) = emu.save_battery_ram() {
+                        eprintln!("failed to save battery RAM: {e}");
+                    }
+                    *control_flow = ControlFlow::Exit;
+                }
 
                 WindowEvent::Resized(size) => {
                     pixels.resize_surface(size.width, size.height).ok();
@@ -71,14 +137,43 @@ fn main() -> Result<()> {
 
             Event::RedrawRequested(_) => {
                 // Run enough cycles for one frame (approx 70224 cycles/frame on DMG)
-                emu.run_frame();
+                if let Err(e) = emu.run_frame() {
+                    eprintln!("CPU halted: {e}");
+                    *control_flow = ControlFlow::Exit;
+                    return;
+                }
 
                 let frame = pixels.frame_mut();
                 frame.copy_from_slice(emu.framebuffer_rgba());
 
+                audio_queue
+                    .lock()
+                    .unwrap()
+                    .extend(emu.drain_audio_samples());
+
+                if args.serial_stdout {
+                    let out = emu.drain_serial_output();
+                    if !out.is_empty() {
+                        use std::io::Write;
+                        std::io::stdout().write_all(&out).ok();
+                        std::io::stdout().flush().ok();
+                    }
+                }
+
                 if pixels.render().is_err() {
                     *control_flow = ControlFlow::Exit;
                 }
+
+                // Also flush battery RAM periodically (~once a second at 60
+                // fps), so a crash or power-cut doesn't lose progress made
+                // since the last clean exit.
+                frames_since_battery_save += 1;
+                if frames_since_battery_save >= 60 {
+                    frames_since_battery_save = 0;
+                    if let Err(e) = emu.save_battery_ram() {
+                        eprintln!("failed to save battery RAM: {e}");
+                    }
+                }
             }
 
             Event::MainEventsCleared => {